@@ -0,0 +1,242 @@
+//! Local JSON API for inspecting and controlling running farmer.
+//!
+//! Endpoints:
+//! * `GET /status` - returns [`Status`]
+//! * `POST /recommitment/pause` - pause recommitment on salt changes
+//! * `POST /recommitment/resume` - resume recommitment and create deferred commitments
+//! * `POST /commitments/<salt in hex>` - create commitment for salt in background
+//! * `POST /shutdown` - gracefully stop farming
+//!
+//! All successful requests respond with current [`Status`].
+//!
+//! API is only served on loopback address. Every request must carry `Authorization: Bearer <token>`
+//! header with token from [`API_TOKEN_FILE`] in data directory (created on first start), `Host`
+//! header matching the address API is served on and no `Origin` header, such that web pages open
+//! in browser on the same machine can't use it.
+use crate::farming::SlotHandlerControl;
+use crate::http::{self, Request, Response};
+use crate::plot::{CommitmentStatus, Plot};
+use crate::rpc::{SlotInfo, SlotNumber};
+use crate::Salt;
+use async_std::net::TcpListener;
+use futures::channel::mpsc;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Name of the file in data directory API token is stored in
+pub const API_TOKEN_FILE: &str = "api-token";
+
+/// Status of running farmer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    /// Address of the node farmer connects to
+    pub node: String,
+    /// Whether farmer is subscribed to slots of the node at the moment
+    pub connected: bool,
+    /// Current salt (hex)
+    pub current_salt: Option<String>,
+    /// Next salt (hex)
+    pub next_salt: Option<String>,
+    /// Whether recommitment on salt changes is paused
+    pub recommitment_paused: bool,
+    /// Statuses of commitments by salt (hex)
+    pub commitments: HashMap<String, CommitmentStatus>,
+    /// Last slot received from the node
+    pub last_slot: Option<SlotInfo>,
+    /// Last solution submitted to the node
+    pub last_solution: Option<SubmittedSolution>,
+}
+
+/// Summary of solution submitted to the node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmittedSolution {
+    /// Slot number solution was submitted for
+    pub slot_number: SlotNumber,
+    /// Index of the encoding in the plot
+    pub nonce: u64,
+    /// Tag (hex)
+    pub tag: String,
+}
+
+/// Farming state updated by farm command and exposed through the API
+#[derive(Debug, Default)]
+pub(crate) struct FarmingState {
+    pub(crate) connected: bool,
+    pub(crate) last_slot: Option<SlotInfo>,
+    pub(crate) last_solution: Option<SubmittedSolution>,
+}
+
+pub(crate) struct Api {
+    /// Address API is served on
+    pub(crate) address: SocketAddr,
+    /// Token requests must be authorized with
+    pub(crate) token: String,
+    pub(crate) node: String,
+    pub(crate) farming_state: Arc<Mutex<FarmingState>>,
+    pub(crate) control: SlotHandlerControl,
+    pub(crate) plot: Plot,
+    pub(crate) shutdown_sender: mpsc::Sender<()>,
+}
+
+impl Api {
+    fn status(&self) -> Status {
+        let farming_state = self.farming_state.lock().unwrap();
+
+        Status {
+            node: self.node.clone(),
+            connected: farming_state.connected,
+            current_salt: self.control.current_salt().map(hex::encode),
+            next_salt: self.control.next_salt().map(hex::encode),
+            recommitment_paused: self.control.is_recommitment_paused(),
+            commitments: self
+                .plot
+                .commitment_statuses()
+                .into_iter()
+                .map(|(salt, status)| (hex::encode(salt), status))
+                .collect(),
+            last_slot: farming_state.last_slot.clone(),
+            last_solution: farming_state.last_solution.clone(),
+        }
+    }
+
+    /// Rejects requests that don't come from local client that knows the token
+    fn check_request(&self, request: &Request) -> Result<(), Response> {
+        // Browsers add `Origin` to cross-origin requests, local clients have no reason to
+        if request.header("Origin").is_some() {
+            return Err(json_response(
+                403,
+                &serde_json::json!({"error": "Cross-origin requests are not allowed"}),
+            ));
+        }
+        // Pages that rebind their domain to loopback address have their domain in `Host`
+        let localhost = format!("localhost:{}", self.address.port());
+        let address = self.address.to_string();
+        if !matches!(request.header("Host"), Some(host) if host == address || host == localhost) {
+            return Err(json_response(
+                403,
+                &serde_json::json!({"error": "Unexpected Host header"}),
+            ));
+        }
+        let is_authorized = request
+            .header("Authorization")
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+            .map(|token| {
+                ring::constant_time::verify_slices_are_equal(
+                    token.trim().as_bytes(),
+                    self.token.as_bytes(),
+                )
+                .is_ok()
+            })
+            .unwrap_or_default();
+        if !is_authorized {
+            return Err(json_response(
+                401,
+                &serde_json::json!({"error": "Missing or invalid API token"}),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn handle(&self, request: Request) -> Response {
+        if let Err(response) = self.check_request(&request) {
+            return response;
+        }
+
+        let path = request.path.trim_end_matches('/');
+        let segments = path.split('/').skip(1).collect::<Vec<_>>();
+
+        let known_path = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["status"]) => true,
+            ("POST", ["recommitment", "pause"]) => {
+                self.control.pause_recommitment();
+                true
+            }
+            ("POST", ["recommitment", "resume"]) => {
+                self.control.resume_recommitment();
+                true
+            }
+            ("POST", ["commitments", salt]) => {
+                let salt: Salt = match hex::decode(salt)
+                    .ok()
+                    .and_then(|salt| salt.as_slice().try_into().ok())
+                {
+                    Some(salt) => salt,
+                    None => {
+                        return json_response(
+                            400,
+                            &serde_json::json!({"error": "Salt must be 8 bytes in hex"}),
+                        );
+                    }
+                };
+                self.control.force_commitment(salt);
+                true
+            }
+            ("POST", ["shutdown"]) => {
+                // If fails - shutdown was already requested, nothing else to do
+                let _ = self.shutdown_sender.clone().try_send(());
+                true
+            }
+            (_, ["status"])
+            | (_, ["recommitment", "pause"])
+            | (_, ["recommitment", "resume"])
+            | (_, ["commitments", _])
+            | (_, ["shutdown"]) => {
+                return json_response(405, &serde_json::json!({"error": "Method not allowed"}));
+            }
+            _ => false,
+        };
+
+        if known_path {
+            json_response(200, &self.status())
+        } else {
+            json_response(404, &serde_json::json!({"error": "Not found"}))
+        }
+    }
+}
+
+fn json_response<T: Serialize>(status: u16, body: &T) -> Response {
+    Response::new(
+        status,
+        "application/json",
+        serde_json::to_vec(body).unwrap(),
+    )
+}
+
+/// Read API token from data directory, new random token is created if there is none yet
+pub(crate) fn open_or_create_token(path: &Path) -> io::Result<String> {
+    let token_file = path.join(API_TOKEN_FILE);
+    match fs::read_to_string(&token_file) {
+        Ok(token) => return Ok(token.trim().to_string()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+    }
+
+    let mut token = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut token)
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "Failed to generate API token"))?;
+    let token = hex::encode(token);
+    // Only readable by the user farmer runs as
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(token_file)?
+        .write_all(token.as_bytes())?;
+
+    Ok(token)
+}
+
+/// Serve API over HTTP on specified listener until returned future is dropped
+pub(crate) async fn serve(listener: TcpListener, api: Api) -> io::Result<()> {
+    http::serve(listener, move |request| api.handle(request)).await
+}
@@ -1,5 +1,146 @@
+//! Commands exposed by `spartan-farmer` binary
+mod bench;
+mod erase_plot;
 mod farm;
+mod info;
 mod plot;
+mod replay;
+mod simulate;
 
-pub(crate) use farm::farm;
-pub(crate) use plot::plot;
+use crate::config::ConfigError;
+use crate::crypto::MnemonicError;
+use crate::plot::PlotError;
+use crate::{Piece, PIECE_SIZE, PRIME_SIZE_BYTES};
+pub use bench::{bench, BenchOptions};
+pub use erase_plot::{erase_plot, EraseMode};
+pub use farm::{farm, FarmOptions};
+pub use info::{info, plot_info, CommitmentInfo, PlotInfo};
+use log::info;
+pub use plot::{plot, PlottingOptions};
+pub use replay::replay;
+use schnorrkel::Keypair;
+pub use simulate::{simulate, SimulationOptions};
+use spartan_codec::Spartan;
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use thiserror::Error;
+
+/// Errors returned by commands
+#[derive(Debug, Error)]
+pub enum CommandError {
+    /// Identity doesn't exist yet
+    #[error("Identity not found, please create it first using plot command")]
+    IdentityNotFound,
+    /// Identity file can't be decoded
+    #[error("Identity is corrupted: {0}")]
+    InvalidIdentity(schnorrkel::SignatureError),
+    /// Existing identity doesn't match provided mnemonic
+    #[error("Existing identity doesn't match provided mnemonic")]
+    IdentityMismatch,
+    /// Invalid mnemonic
+    #[error(transparent)]
+    Mnemonic(#[from] MnemonicError),
+    /// Genesis piece doesn't exist yet
+    #[error(
+        "Genesis piece not found, please run plot command with the same seed first (existing plot \
+        is not re-plotted)"
+    )]
+    GenesisPieceNotFound,
+    /// Genesis piece file can't be decoded
+    #[error("Genesis piece is corrupted")]
+    InvalidGenesisPiece,
+    /// Existing plot was created with a different seed
+    #[error("Existing plot was created with a different seed")]
+    GenesisPieceMismatch,
+    /// Node closed slot info subscription without sending any slots
+    #[error("Node didn't send any slots")]
+    SlotInfoNotReceived,
+    /// Plot doesn't have any pieces in it
+    #[error("Plot is empty, please create it first using plot command")]
+    PlotEmpty,
+    /// API address is not a loopback address
+    #[error("API can only be served on loopback address, not {0}")]
+    ApiAddressNotLoopback(SocketAddr),
+    /// Destructive operation was not confirmed by user
+    #[error("Operation was not confirmed")]
+    NotConfirmed,
+    /// Record file has invalid line in it
+    #[error("Invalid record on line {line}: {error}")]
+    InvalidRecord {
+        /// Line number, starting from 1
+        line: usize,
+        /// Decoding error
+        error: serde_json::Error,
+    },
+    /// Configuration error
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    /// Plot error
+    #[error(transparent)]
+    Plot(#[from] PlotError),
+    /// RPC error
+    #[error("RPC error: {0}")]
+    Rpc(#[from] jsonrpsee::ws_client::Error),
+    /// I/O error
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+impl CommandError {
+    /// Process exit code corresponding to this error, follows `sysexits.h` conventions
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // EX_USAGE
+            CommandError::Mnemonic(_)
+            | CommandError::ApiAddressNotLoopback(_)
+            | CommandError::NotConfirmed => 64,
+            // EX_DATAERR
+            CommandError::InvalidIdentity(_)
+            | CommandError::IdentityMismatch
+            | CommandError::InvalidGenesisPiece
+            | CommandError::GenesisPieceMismatch
+            | CommandError::InvalidRecord { .. } => 65,
+            // EX_NOINPUT
+            CommandError::IdentityNotFound
+            | CommandError::GenesisPieceNotFound
+            | CommandError::PlotEmpty => 66,
+            // EX_UNAVAILABLE
+            CommandError::Rpc(_) | CommandError::SlotInfoNotReceived => 69,
+            // EX_TEMPFAIL
+            CommandError::Plot(PlotError::Locked { .. }) => 75,
+            // EX_IOERR
+            CommandError::Plot(_) | CommandError::Io(_) => 74,
+            // EX_CONFIG
+            CommandError::Config(_) => 78,
+        }
+    }
+}
+
+/// Open keypair previously created by plot command
+fn open_keypair(path: &Path) -> Result<Keypair, CommandError> {
+    let identity_file = path.join("identity.bin");
+    if !identity_file.exists() {
+        return Err(CommandError::IdentityNotFound);
+    }
+
+    info!("Opening existing keypair");
+    Keypair::from_bytes(&fs::read(identity_file)?).map_err(CommandError::InvalidIdentity)
+}
+
+/// Create Spartan instance from genesis piece previously stored by plot command
+fn open_spartan(path: &Path) -> Result<Spartan<PRIME_SIZE_BYTES, PIECE_SIZE>, CommandError> {
+    let genesis_piece_file = path.join("genesis-piece.bin");
+    if !genesis_piece_file.exists() {
+        return Err(CommandError::GenesisPieceNotFound);
+    }
+
+    let genesis_piece: Piece = fs::read(genesis_piece_file)?
+        .as_slice()
+        .try_into()
+        .map_err(|_| CommandError::InvalidGenesisPiece)?;
+
+    Ok(Spartan::<PRIME_SIZE_BYTES, PIECE_SIZE>::new(genesis_piece))
+}
@@ -0,0 +1,183 @@
+use crate::commands::CommandError;
+use crate::plot::{Plot, PlotError};
+use crate::{crypto, Piece, Salt, Tag, ENCODE_ROUNDS, PIECE_SIZE, PRIME_SIZE_BYTES};
+use futures::channel::oneshot;
+use log::info;
+use rayon::prelude::*;
+use ring::digest;
+use spartan_codec::Spartan;
+use std::convert::TryInto;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const SALT: Salt = [1u8; PRIME_SIZE_BYTES];
+
+/// Options of bench command
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Number of pieces in temporary plot used for benchmarking
+    pub pieces: u64,
+    /// Number of searches to measure `find_by_range` latency with
+    pub searches: u64,
+    /// Number of pieces in the plot to project time to plot and time to recommit for
+    pub plot_pieces: u64,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            pieces: 4096,
+            searches: 1000,
+            plot_pieces: 256_000,
+        }
+    }
+}
+
+/// Benchmark encoding, tagging, plot I/O, commitment creation and search using temporary plot in
+/// specified path (so that target disk is measured) and project time to plot and time to
+/// recommit for plot of requested size.
+///
+/// NOTE: Plot is read back right after it was written, so read throughput is likely to be served
+/// from OS page cache.
+pub async fn bench(path: PathBuf, options: BenchOptions) -> Result<(), CommandError> {
+    let pieces = options.pieces.max(1);
+    let bench_path = path.join("bench");
+    if bench_path.exists() {
+        fs::remove_dir_all(&bench_path)?;
+    }
+    fs::create_dir_all(&bench_path)?;
+
+    let result = run(bench_path.clone(), pieces, &options).await;
+    let cleanup_result = fs::remove_dir_all(bench_path);
+
+    result?;
+    cleanup_result?;
+
+    Ok(())
+}
+
+async fn run(path: PathBuf, pieces: u64, options: &BenchOptions) -> Result<(), CommandError> {
+    let spartan =
+        Spartan::<PRIME_SIZE_BYTES, PIECE_SIZE>::new(crypto::genesis_piece_from_seed("bench"));
+    let farmer_id = [0u8; PRIME_SIZE_BYTES];
+    let threads = rayon::current_num_threads();
+
+    info!("Benchmarking encoding on 1 core");
+    let single_core_pieces = (pieces / threads as u64).max(1);
+    let started = Instant::now();
+    for index in 0..single_core_pieces {
+        spartan.encode(farmer_id, index, ENCODE_ROUNDS);
+    }
+    let encode_per_core = rate(single_core_pieces, started.elapsed());
+
+    info!("Benchmarking encoding on {} cores", threads);
+    let started = Instant::now();
+    let encodings: Vec<Piece> = (0..pieces)
+        .into_par_iter()
+        .map(|index| spartan.encode(farmer_id, index, ENCODE_ROUNDS))
+        .collect();
+    let encode_all_cores = rate(pieces, started.elapsed());
+
+    info!("Benchmarking tagging");
+    let started = Instant::now();
+    for encoding in &encodings {
+        crypto::create_tag(encoding, &SALT);
+    }
+    let tag_rate = rate(pieces, started.elapsed());
+
+    let plot = Plot::open_or_create(&path.into()).await?;
+
+    info!("Benchmarking plot writes");
+    let started = Instant::now();
+    plot.write_many(encodings, 0).await?;
+    let write_rate = rate(pieces, started.elapsed());
+
+    info!("Benchmarking plot reads");
+    let started = Instant::now();
+    plot.read_pieces(0, pieces).await?;
+    let read_rate = rate(pieces, started.elapsed());
+
+    info!("Benchmarking commitment creation");
+    let started = Instant::now();
+    plot.create_commitment(SALT).await?;
+    let commitment_rate = rate(pieces, started.elapsed());
+
+    info!("Benchmarking search");
+    // One tag falls into solution range on average, same as in the network
+    let range = u64::MAX / pieces;
+    let mut latencies = Vec::with_capacity(options.searches as usize);
+    for search in 0..options.searches {
+        let target: Tag = digest::digest(&digest::SHA256, &search.to_le_bytes()).as_ref()
+            [..PRIME_SIZE_BYTES]
+            .try_into()
+            .unwrap();
+        let started = Instant::now();
+        plot.find_by_range(target, range, SALT).await?;
+        latencies.push(started.elapsed());
+    }
+
+    let (tx, rx) = oneshot::channel();
+
+    let _handler = plot.on_close(move || {
+        let _ = tx.send(());
+    });
+
+    drop(plot);
+
+    rx.await.map_err(|_| PlotError::Closed)?;
+
+    latencies.sort_unstable();
+    let percentile = |percentile: usize| -> f64 {
+        latencies
+            .get((latencies.len() * percentile / 100).min(latencies.len().saturating_sub(1)))
+            .map(|latency| latency.as_secs_f64() * 1000_f64)
+            .unwrap_or_default()
+    };
+
+    info!("Benchmark results ({} pieces):", pieces);
+    info!(
+        "Encoding: {:.0} pieces/sec per core, {:.0} pieces/sec on {} cores",
+        encode_per_core, encode_all_cores, threads
+    );
+    info!("Tagging: {:.0} pieces/sec", tag_rate);
+    info!(
+        "Plot write: {:.0} pieces/sec ({:.2} MB/sec)",
+        write_rate,
+        megabytes(write_rate)
+    );
+    info!(
+        "Plot read: {:.0} pieces/sec ({:.2} MB/sec)",
+        read_rate,
+        megabytes(read_rate)
+    );
+    info!("Commitment creation: {:.0} pieces/sec", commitment_rate);
+    info!(
+        "Search latency: p50 {:.3} ms, p90 {:.3} ms, p99 {:.3} ms, max {:.3} ms",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        percentile(100)
+    );
+
+    // Encoding and writing happen concurrently during plotting, slower of the two wins
+    let plot_rate = encode_all_cores.min(write_rate);
+    info!(
+        "Projected for {} pieces ({:.2} GB): time to plot {:.1} minutes, time to recommit {:.1} \
+        minutes",
+        options.plot_pieces,
+        (options.plot_pieces * PIECE_SIZE as u64) as f64 / 1_000_000_000_f64,
+        options.plot_pieces as f64 / plot_rate / 60_f64,
+        options.plot_pieces as f64 / commitment_rate / 60_f64
+    );
+
+    Ok(())
+}
+
+fn rate(pieces: u64, elapsed: Duration) -> f64 {
+    pieces as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+fn megabytes(pieces_per_second: f64) -> f64 {
+    pieces_per_second * PIECE_SIZE as f64 / 1_000_000_f64
+}
@@ -0,0 +1,144 @@
+use crate::api::API_TOKEN_FILE;
+use crate::commands::CommandError;
+use crate::plot::{DirectoryLock, LOCK_FILE_NAME};
+use log::info;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// What should be erased by erase plot command
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EraseMode {
+    /// Plot, its commitments, identity, genesis piece and API token
+    All,
+    /// Plot and its commitments, identity and genesis piece are kept
+    Plot,
+    /// Only commitments, they will be re-created by farmer
+    Commitments,
+}
+
+/// Erase files in specified path according to `mode`, files that don't exist are skipped.
+///
+/// Unless `yes` is `true`, list of files is printed and user is asked for confirmation. Erasing is
+/// refused while data directory is used by another process.
+pub fn erase_plot(path: &Path, mode: EraseMode, yes: bool) -> Result<(), CommandError> {
+    if !path.exists() {
+        info!("Nothing to erase");
+        return Ok(());
+    }
+
+    let _lock = DirectoryLock::acquire(path)?;
+
+    let mut targets = vec![path.join("plot-tags")];
+    if mode != EraseMode::Commitments {
+        targets.push(path.join("plot.bin"));
+    }
+    if mode == EraseMode::All {
+        targets.push(path.join("identity.bin"));
+        targets.push(path.join("genesis-piece.bin"));
+        targets.push(path.join(API_TOKEN_FILE));
+    }
+    let targets = targets
+        .into_iter()
+        .filter(|target| target.exists())
+        .collect::<Vec<_>>();
+
+    if targets.is_empty() {
+        info!("Nothing to erase");
+    } else {
+        if !yes && !confirm(&targets)? {
+            return Err(CommandError::NotConfirmed);
+        }
+
+        for target in targets {
+            info!("Erasing {}", target.display());
+            if target.is_dir() {
+                fs::remove_dir_all(target)?;
+            } else {
+                fs::remove_file(target)?;
+            }
+        }
+        info!("Done");
+    }
+
+    // Don't leave lock file behind if it is the only thing left in data directory
+    if mode == EraseMode::All {
+        let mut entries = fs::read_dir(path)?;
+        if entries.all(|entry| {
+            entry
+                .map(|entry| entry.file_name() == LOCK_FILE_NAME)
+                .unwrap_or_default()
+        }) {
+            fs::remove_file(path.join(LOCK_FILE_NAME))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn confirm(targets: &[PathBuf]) -> io::Result<bool> {
+    println!("Following will be erased:");
+    for target in targets {
+        println!("  {}", target.display());
+    }
+    if targets
+        .iter()
+        .any(|target| target.ends_with("identity.bin"))
+    {
+        println!(
+            "Identity can only be restored from mnemonic phrase, without it plot can't be \
+            recreated!"
+        );
+    }
+    print!("Type \"yes\" to continue: ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+
+    Ok(answer.trim() == "yes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plot::PlotError;
+
+    #[test]
+    fn test_erase_plot() {
+        let path = PathBuf::from("target").join("erase_plot");
+        drop(fs::remove_dir_all(&path));
+        fs::create_dir_all(path.join("plot-tags")).unwrap();
+        fs::write(path.join("plot.bin"), [0u8; 16]).unwrap();
+        fs::write(path.join("identity.bin"), [0u8; 16]).unwrap();
+
+        {
+            let _lock = DirectoryLock::acquire(&path).unwrap();
+            assert!(matches!(
+                erase_plot(&path, EraseMode::All, true),
+                Err(CommandError::Plot(PlotError::Locked { pid: Some(_) }))
+            ));
+        }
+
+        erase_plot(&path, EraseMode::Commitments, true).unwrap();
+        assert!(!path.join("plot-tags").exists());
+        assert!(path.join("plot.bin").exists());
+
+        // Missing commitments and genesis piece are skipped
+        erase_plot(&path, EraseMode::Plot, true).unwrap();
+        assert!(!path.join("plot.bin").exists());
+        assert!(path.join("identity.bin").exists());
+
+        erase_plot(&path, EraseMode::All, true).unwrap();
+        assert!(!path.join("identity.bin").exists());
+        // Nothing is left behind, including lock file
+        assert_eq!(fs::read_dir(&path).unwrap().count(), 0);
+
+        // Empty and missing data directories have nothing to erase
+        erase_plot(&path, EraseMode::All, true).unwrap();
+        assert_eq!(fs::read_dir(&path).unwrap().count(), 0);
+        fs::remove_dir_all(&path).unwrap();
+        erase_plot(&path, EraseMode::All, true).unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -1,283 +1,397 @@
-use crate::plot::Plot;
-use crate::{crypto, Salt, Tag, PRIME_SIZE_BYTES, SIGNING_CONTEXT};
+use crate::api::{self, Api, FarmingState, SubmittedSolution};
+use crate::commands::CommandError;
+use crate::config::NodeConfig;
+use crate::farming::{SlotHandler, SlotRecord};
+use crate::metrics::{self, Metrics};
+use crate::plot::{Plot, PlotError, PlotOptions};
+use crate::rpc::{ProposedProofOfSpaceResponse, SlotInfo};
+use async_std::net::{SocketAddr, TcpListener};
 use async_std::task;
-use futures::channel::oneshot;
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, Either};
+use futures::{FutureExt, StreamExt};
 use jsonrpsee::ws_client::traits::{Client, SubscriptionClient};
 use jsonrpsee::ws_client::v2::params::JsonRpcParams;
+use jsonrpsee::ws_client::WsClient;
 use jsonrpsee::ws_client::{Subscription, WsClientBuilder};
-use log::{debug, error, info, trace, warn};
-use ring::digest;
-use schnorrkel::Keypair;
-use serde::{Deserialize, Serialize};
-use std::convert::TryInto;
-use std::fs;
+use log::{debug, info, warn};
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::path::PathBuf;
-use std::time::Instant;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Lower bound for time given to search for solution, such that bursts of slot notifications
+/// don't make deadlines unrealistically short
+const MIN_SLOT_DEADLINE: Duration = Duration::from_millis(500);
+/// Number of slot notifications client buffers before dropping subscription, large enough to
+/// survive a burst of notifications until they are received and superseded ones skipped
+const MAX_PENDING_SLOTS: usize = 100;
+/// Delay before the first attempt to reconnect to the node, doubled after every failed attempt
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound for delay between attempts to reconnect to the node
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Estimate of slot duration based on intervals between slot notifications
+#[derive(Default)]
+struct SlotDuration {
+    last_slot_received: Option<Instant>,
+    estimate: Option<Duration>,
+}
 
-type SlotNumber = u64;
+impl SlotDuration {
+    fn slot_received(&mut self, received: Instant) {
+        if let Some(last_slot_received) = self.last_slot_received.replace(received) {
+            let interval = received.saturating_duration_since(last_slot_received);
+            // Smoothed such that single late or early notification doesn't affect it much
+            self.estimate.replace(match self.estimate {
+                Some(estimate) => (estimate * 7 + interval) / 8,
+                None => interval,
+            });
+        }
+    }
 
-#[derive(Debug, Serialize)]
-struct Solution {
-    public_key: [u8; 32],
-    nonce: u64,
-    encoding: Vec<u8>,
-    signature: Vec<u8>,
-    tag: Tag,
+    /// Solution is useless after the next slot arrives, returns `None` until slot duration is known
+    fn deadline(&self, received: Instant) -> Option<Instant> {
+        self.estimate
+            .map(|estimate| received + estimate.max(MIN_SLOT_DEADLINE))
+    }
 }
 
-/// Proposed proof of space consisting of solution and farmer's secret key for block signing
-#[derive(Debug, Serialize)]
-struct ProposedProofOfSpaceResponse {
-    /// Slot number
-    slot_number: SlotNumber,
-    /// Solution (if present) from farmer's plot corresponding to slot number above
-    solution: Option<Solution>,
-    // Secret key, used for signing blocks on the client node
-    secret_key: Vec<u8>,
+/// Options of farm command
+#[derive(Debug, Clone)]
+pub struct FarmOptions {
+    /// WebSocket RPC address of the node
+    pub ws_server: String,
+    /// Number of attempts to reconnect after connection to the node is lost before giving up,
+    /// zero disables reconnection such that farming stops once node disconnects
+    pub reconnect_attempts: u32,
+    /// If specified, every received slot and solution proposed for it is appended to this file,
+    /// one JSON object per line, such that it can be replayed later
+    pub record_file: Option<PathBuf>,
+    /// If specified, Prometheus metrics are served over HTTP on this address at `/metrics`
+    pub metrics_address: Option<SocketAddr>,
+    /// If specified, local status/control JSON API is served over HTTP on this address, see
+    /// [`crate::api`] for details, must be a loopback address
+    pub api_address: Option<SocketAddr>,
+    /// Read plot through memory mapping, see [`PlotOptions::mmap`]
+    pub mmap: bool,
+    /// Keep in-memory index of tags, see [`PlotOptions::tag_index`]
+    pub tag_index: bool,
+    /// Number of pieces read and tagged at once during recommitment, see
+    /// [`PlotOptions::batch_size`], default is used if not specified
+    pub batch_size: Option<u64>,
+    /// Capacity of plot's request queues, see [`PlotOptions::queue_size`], default is used if not
+    /// specified
+    pub queue_size: Option<usize>,
 }
 
-/// Information about new slot that just arrived
-#[derive(Debug, Deserialize)]
-struct SlotInfo {
-    /// Slot number
-    slot_number: SlotNumber,
-    /// Slot challenge
-    challenge: [u8; PRIME_SIZE_BYTES],
-    /// Salt
-    salt: Salt,
-    /// Salt for the next eon
-    next_salt: Option<Salt>,
-    /// Acceptable solution range
-    solution_range: u64,
+impl Default for FarmOptions {
+    fn default() -> Self {
+        Self {
+            ws_server: NodeConfig::default().ws_server,
+            reconnect_attempts: NodeConfig::default().reconnect_attempts,
+            record_file: None,
+            metrics_address: None,
+            api_address: None,
+            mmap: false,
+            tag_index: false,
+            batch_size: None,
+            queue_size: None,
+        }
+    }
+}
+
+async fn connect(ws_server: &str) -> Result<WsClient, jsonrpsee::ws_client::Error> {
+    WsClientBuilder::default()
+        .max_notifs_per_subscription(MAX_PENDING_SLOTS)
+        .build(ws_server)
+        .await
 }
 
 /// Start farming by using plot in specified path and connecting to WebSocket server at specified
 /// address.
-pub(crate) async fn farm(path: PathBuf, ws_server: &str) -> Result<(), Box<dyn std::error::Error>> {
-    info!("Connecting to RPC server");
-    let client = WsClientBuilder::default().build(ws_server).await?;
-
-    let identity_file = path.join("identity.bin");
-    if !identity_file.exists() {
-        panic!("Identity not found, please create it first using plot command");
+///
+/// Farmer reconnects to the node if connection is lost while farming, failure to connect initially
+/// is returned as an error right away.
+pub async fn farm(path: PathBuf, options: FarmOptions) -> Result<(), CommandError> {
+    if let Some(address) = options.api_address {
+        if !address.ip().is_loopback() {
+            return Err(CommandError::ApiAddressNotLoopback(address));
+        }
     }
 
-    info!("Opening existing keypair");
-    let keypair =
-        Keypair::from_bytes(&fs::read(identity_file)?).map_err(|error| error.to_string())?;
-    let public_key_hash = crypto::hash_public_key(&keypair.public);
-    let ctx = schnorrkel::context::signing_context(SIGNING_CONTEXT);
+    let metrics = Metrics::new();
+
+    info!("Connecting to RPC server");
+    let client = connect(&options.ws_server).await?;
+    metrics.rpc_connected();
+
+    let keypair = super::open_keypair(&path)?;
+    let spartan = super::open_spartan(&path)?;
 
     info!("Opening plot");
-    let plot = Plot::open_or_create(&path.into()).await?;
+    let default_options = PlotOptions::default();
+    let plot = Plot::open_with_options(
+        &path.clone().into(),
+        PlotOptions {
+            mmap: options.mmap,
+            tag_index: options.tag_index,
+            batch_size: options.batch_size.unwrap_or(default_options.batch_size),
+            queue_size: options.queue_size.unwrap_or(default_options.queue_size),
+            ..default_options
+        },
+    )
+    .await?;
 
     if plot.is_empty().await {
-        panic!("Plot is empty, please create it first using plot command");
+        return Err(CommandError::PlotEmpty);
     }
 
-    info!("Subscribing to slot info notifications");
-    let mut sub: Subscription<SlotInfo> = client
-        .subscribe(
-            "poc_subscribeSlotInfo",
-            JsonRpcParams::NoParams,
-            "poc_unsubscribeSlotInfo",
-        )
-        .await?;
-
-    let mut current_salt = None;
-    let mut next_salt = None;
-
-    while let Some(slot_info) = sub.next().await {
-        debug!("New slot: {:?}", slot_info);
-
-        if current_salt.is_none() {
-            let mut salts = vec![slot_info.salt];
-            if let Some(salt) = slot_info.next_salt {
-                salts.push(salt);
-            }
-            plot.retain_commitments(salts).await?;
+    let mut record_file = match options.record_file {
+        Some(record_file) => {
+            info!("Recording slots to {}", record_file.display());
+            Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(record_file)?,
+            )
         }
-
-        // Check if current salt has changed
-        if current_salt != Some(slot_info.salt) {
-            // If previous `next_salt` is the same as current (expected behavior) remove old
-            // commitment
-            if next_salt == Some(slot_info.salt) {
-                let old_salt = current_salt.replace(slot_info.salt);
-                if let Some(old_salt) = old_salt {
-                    info!(
-                        "Salt {} is out of date, removing commitment",
-                        hex::encode(old_salt)
-                    );
-
-                    task::spawn({
-                        let plot = plot.clone();
-
-                        async move {
-                            if let Err(error) = plot.remove_commitment(old_salt).await {
-                                error!(
-                                    "Failed to remove old commitment for {}: {}",
-                                    hex::encode(old_salt),
-                                    error
-                                );
-                            }
-                        }
-                    })
-                    .await;
-                }
-            } else {
-                // `next_salt` is not the same as new salt, need to re-commit
-                task::spawn({
-                    let salt = slot_info.salt;
-                    let plot = plot.clone();
-
-                    async move {
-                        let started = Instant::now();
-                        info!(
-                            "Salt updated to {}, recommitting in background",
-                            hex::encode(salt)
-                        );
-
-                        if let Err(error) = plot.create_commitment(salt).await {
-                            error!(
-                                "Failed to create commitment for {}: {}",
-                                hex::encode(salt),
-                                error
-                            );
-                        } else {
-                            info!(
-                                "Finished recommitment for {} in {} seconds",
-                                hex::encode(salt),
-                                started.elapsed().as_secs_f32()
-                            );
+        None => None,
+    };
+
+    let metrics_server = match options.metrics_address {
+        Some(address) => {
+            let listener = TcpListener::bind(address).await?;
+            info!(
+                "Serving metrics on http://{}/metrics",
+                listener.local_addr()?
+            );
+            Some(task::spawn(metrics::serve(
+                listener,
+                metrics.clone(),
+                plot.clone(),
+            )))
+        }
+        None => None,
+    };
+
+    let secret_key = keypair.secret.to_bytes().to_vec();
+    let mut slot_handler = SlotHandler::new(plot.clone(), keypair, spartan, metrics.clone());
+
+    let farming_state = Arc::new(Mutex::new(FarmingState::default()));
+    let (shutdown_sender, mut shutdown_receiver) = mpsc::channel(1);
+    let api_server = match options.api_address {
+        Some(address) => {
+            let token = api::open_or_create_token(&path)?;
+            let listener = TcpListener::bind(address).await?;
+            let address = listener.local_addr()?;
+            info!(
+                "Serving API on http://{}, token is in {}",
+                address,
+                path.join(api::API_TOKEN_FILE).display()
+            );
+            Some(task::spawn(api::serve(
+                listener,
+                Api {
+                    address,
+                    token,
+                    node: options.ws_server.clone(),
+                    farming_state: Arc::clone(&farming_state),
+                    control: slot_handler.control(),
+                    plot: plot.clone(),
+                    shutdown_sender: shutdown_sender.clone(),
+                },
+            )))
+        }
+        None => None,
+    };
+
+    let ws_server = &options.ws_server;
+    let reconnect_attempts = options.reconnect_attempts;
+    let result: Result<(), CommandError> = async {
+        let mut client = client;
+        loop {
+            // Whether shutdown was requested, otherwise connection to the node was lost
+            let result: Result<bool, CommandError> = async {
+                info!("Subscribing to slot info notifications");
+                let mut sub: Subscription<SlotInfo> = client
+                    .subscribe(
+                        "poc_subscribeSlotInfo",
+                        JsonRpcParams::NoParams,
+                        "poc_unsubscribeSlotInfo",
+                    )
+                    .await?;
+                farming_state.lock().unwrap().connected = true;
+
+                // Slots are received in background, such that time of arrival is known precisely
+                // and slots that piled up while farmer was busy can be skipped
+                let (slots_sender, slots_receiver) = mpsc::unbounded();
+                let mut slots_receiver = slots_receiver.fuse();
+                let slots_forwarder = task::spawn(async move {
+                    while let Some(slot_info) = sub.next().await {
+                        if slots_sender
+                            .unbounded_send((slot_info, Instant::now()))
+                            .is_err()
+                        {
+                            break;
                         }
                     }
                 });
 
-                let old_salt = current_salt.replace(slot_info.salt);
-                if let Some(old_salt) = old_salt {
-                    warn!(
-                        "New salt {} is not the same as previously known next salt {:?}",
-                        hex::encode(slot_info.salt),
-                        next_salt.map(hex::encode)
-                    );
-                    info!(
-                        "Salt {} is out of date, removing commitment",
-                        hex::encode(old_salt)
-                    );
-
-                    task::spawn({
-                        let plot = plot.clone();
-
-                        async move {
-                            if let Err(error) = plot.remove_commitment(old_salt).await {
-                                error!(
-                                    "Failed to remove old commitment for {}: {}",
-                                    hex::encode(old_salt),
-                                    error
-                                );
+                // Slot duration can't be estimated across the gap while farmer was disconnected
+                let mut slot_duration = SlotDuration::default();
+                let result: Result<bool, CommandError> = try {
+                    loop {
+                        let (mut slot_info, mut received) =
+                            match future::select(slots_receiver.next(), shutdown_receiver.next())
+                                .await
+                            {
+                                Either::Left((Some(slot), _)) => slot,
+                                Either::Left((None, _)) => {
+                                    break false;
+                                }
+                                Either::Right(_) => {
+                                    info!("Shutdown requested, stopping farming");
+                                    break true;
+                                }
+                            };
+                        slot_duration.slot_received(received);
+                        // Only the newest slot matters, proposals for older slots would be too late
+                        while let Some(Some((newer_slot_info, newer_received))) =
+                            slots_receiver.next().now_or_never()
+                        {
+                            debug!("Skipping superseded slot {}", slot_info.slot_number);
+                            metrics.slot_skipped();
+                            // Recorded nonetheless, such that every received slot can be replayed
+                            if let Some(record_file) = &mut record_file {
+                                let record = SlotRecord {
+                                    slot_info,
+                                    solution: None,
+                                };
+                                writeln!(
+                                    record_file,
+                                    "{}",
+                                    serde_json::to_string(&record).unwrap()
+                                )?;
                             }
+                            slot_info = newer_slot_info;
+                            received = newer_received;
+                            slot_duration.slot_received(received);
                         }
-                    })
-                    .await;
-                }
-            }
-        }
-        if let Some(new_next_salt) = slot_info.next_salt {
-            if Some(new_next_salt) != next_salt {
-                let old_salt = next_salt.replace(new_next_salt);
-                if old_salt != current_salt {
-                    if let Some(old_salt) = old_salt {
-                        warn!(
-                            "Previous next salt {} is out of date (current is {:?}), \
-                            removing commitment",
-                            hex::encode(old_salt),
-                            current_salt.map(hex::encode)
-                        );
-
-                        task::spawn({
-                            let plot = plot.clone();
-
-                            async move {
-                                if let Err(error) = plot.remove_commitment(old_salt).await {
-                                    error!(
-                                        "Failed to remove old commitment for {}: {}",
-                                        hex::encode(old_salt),
-                                        error
-                                    );
-                                }
+                        debug!("New slot: {:?}", slot_info);
+                        farming_state.lock().unwrap().last_slot = Some(slot_info.clone());
+
+                        let solution = match slot_duration.deadline(received) {
+                            Some(deadline) => {
+                                slot_handler.handle_slot_until(&slot_info, deadline).await?
                             }
-                        })
-                        .await;
-                    }
-                }
+                            None => slot_handler.handle_slot(&slot_info).await?,
+                        };
+
+                        client
+                            .request(
+                                "poc_proposeProofOfSpace",
+                                JsonRpcParams::Array(vec![serde_json::to_value(
+                                    &ProposedProofOfSpaceResponse {
+                                        slot_number: slot_info.slot_number,
+                                        solution: solution.clone(),
+                                        secret_key: secret_key.clone(),
+                                    },
+                                )
+                                .unwrap()]),
+                            )
+                            .await?;
+                        metrics.observe_slot_latency(received.elapsed());
+                        if let Some(solution) = &solution {
+                            metrics.solution_submitted();
+                            farming_state.lock().unwrap().last_solution = Some(SubmittedSolution {
+                                slot_number: slot_info.slot_number,
+                                nonce: solution.nonce,
+                                tag: hex::encode(solution.tag),
+                            });
+                        }
 
-                task::spawn({
-                    let plot = plot.clone();
-
-                    async move {
-                        let started = Instant::now();
-                        info!(
-                            "Salt will update to {} soon, recommitting in background",
-                            hex::encode(new_next_salt)
-                        );
-                        if let Err(error) = plot.create_commitment(new_next_salt).await {
-                            error!(
-                                "Recommitting salt in background failed for {}: {}",
-                                hex::encode(new_next_salt),
-                                error
-                            );
-                            return;
+                        if let Some(record_file) = &mut record_file {
+                            let record = SlotRecord {
+                                slot_info,
+                                solution,
+                            };
+                            writeln!(record_file, "{}", serde_json::to_string(&record).unwrap())?;
                         }
-                        info!(
-                            "Finished recommitment in background for {} in {} seconds",
-                            hex::encode(new_next_salt),
-                            started.elapsed().as_secs_f32()
-                        );
                     }
-                });
-            }
-        }
-
-        let local_challenge = derive_local_challenge(&slot_info.challenge, &public_key_hash);
-
-        let solution = match plot
-            .find_by_range(local_challenge, slot_info.solution_range, slot_info.salt)
-            .await?
-        {
-            Some((tag, index)) => {
-                let encoding = plot.read(index).await?;
-                let solution = Solution {
-                    public_key: keypair.public.to_bytes(),
-                    nonce: index,
-                    encoding: encoding.to_vec(),
-                    signature: keypair.sign(ctx.bytes(&tag)).to_bytes().to_vec(),
-                    tag,
                 };
+                // Drops subscription
+                slots_forwarder.cancel().await;
 
-                debug!("Solution found");
-                trace!("Solution found: {:?}", solution);
+                result
+            }
+            .await;
+            farming_state.lock().unwrap().connected = false;
 
-                Some(solution)
+            let error = match result {
+                Ok(true) => {
+                    return Ok(());
+                }
+                Ok(false) => None,
+                Err(CommandError::Rpc(error)) => Some(error),
+                Err(error) => {
+                    return Err(error);
+                }
+            };
+            if reconnect_attempts == 0 {
+                info!("Disconnected from RPC server, stopping farming");
+                return error.map_or(Ok(()), |error| Err(error.into()));
             }
-            None => {
-                debug!("Solution not found");
-                None
+            match &error {
+                Some(error) => warn!("Disconnected from RPC server: {}", error),
+                None => warn!("Disconnected from RPC server"),
             }
-        };
-
-        client
-            .request(
-                "poc_proposeProofOfSpace",
-                JsonRpcParams::Array(vec![serde_json::to_value(&ProposedProofOfSpaceResponse {
-                    slot_number: slot_info.slot_number,
-                    solution,
-                    secret_key: keypair.secret.to_bytes().into(),
-                })
-                .unwrap()]),
-            )
-            .await?;
+
+            let mut delay = RECONNECT_DELAY;
+            let mut attempt = 1;
+            client = loop {
+                info!(
+                    "Reconnecting to RPC server in {:?} (attempt {}/{})",
+                    delay, attempt, reconnect_attempts
+                );
+                if let Either::Right(_) =
+                    future::select(Box::pin(task::sleep(delay)), shutdown_receiver.next()).await
+                {
+                    info!("Shutdown requested, stopping farming");
+                    return Ok(());
+                }
+                match connect(ws_server).await {
+                    Ok(client) => {
+                        break client;
+                    }
+                    Err(error) if attempt < reconnect_attempts => {
+                        warn!("Failed to reconnect to RPC server: {}", error);
+                        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                        attempt += 1;
+                    }
+                    Err(error) => {
+                        return Err(error.into());
+                    }
+                }
+            };
+            info!("Reconnected to RPC server");
+            metrics.rpc_connected();
+        }
+    }
+    .await;
+    farming_state.lock().unwrap().connected = false;
+    drop(shutdown_sender);
+
+    // Servers hold plot instance, stop them before closing the plot
+    if let Some(metrics_server) = metrics_server {
+        metrics_server.cancel().await;
     }
+    if let Some(api_server) = api_server {
+        api_server.cancel().await;
+    }
+    drop(slot_handler);
 
     let (tx, rx) = oneshot::channel();
 
@@ -287,19 +401,7 @@ pub(crate) async fn farm(path: PathBuf, ws_server: &str) -> Result<(), Box<dyn s
 
     drop(plot);
 
-    rx.await?;
-
-    Ok(())
-}
+    rx.await.map_err(|_| PlotError::Closed)?;
 
-fn derive_local_challenge(global_challenge: &[u8], farmer_id: &[u8]) -> [u8; 8] {
-    digest::digest(&digest::SHA256, &{
-        let mut data = Vec::with_capacity(global_challenge.len() + farmer_id.len());
-        data.extend_from_slice(global_challenge);
-        data.extend_from_slice(farmer_id);
-        data
-    })
-    .as_ref()[..8]
-        .try_into()
-        .unwrap()
+    result
 }
@@ -0,0 +1,259 @@
+use crate::commands::CommandError;
+use crate::plot::{self, CommitmentStatus};
+use crate::{crypto, PIECE_SIZE};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Information about plot directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlotInfo {
+    /// Number of pieces in the plot
+    pub piece_count: u64,
+    /// Size of the plot file in bytes
+    pub plot_size: u64,
+    /// Public key of identity (hex)
+    pub public_key: Option<String>,
+    /// Farmer ID derived from public key (hex)
+    pub farmer_id: Option<String>,
+    /// Commitments sorted by salt
+    pub commitments: Vec<CommitmentInfo>,
+    /// Inconsistencies found in plot directory
+    pub issues: Vec<String>,
+}
+
+/// Information about commitment stored in plot directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitmentInfo {
+    /// Salt (hex)
+    pub salt: String,
+    /// Status as recorded in commitments metadata
+    pub status: CommitmentStatus,
+    /// Size of the commitment on disk in bytes
+    pub size: u64,
+}
+
+/// Print information about plot in specified path: pieces, identity, commitments and any
+/// inconsistencies found, in JSON if `json` is `true`.
+pub fn info(path: &Path, json: bool) -> Result<(), CommandError> {
+    let plot_info = plot_info(path)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&plot_info).unwrap());
+        return Ok(());
+    }
+
+    println!("Path: {}", path.display());
+    println!(
+        "Plot: {} pieces, {} bytes",
+        plot_info.piece_count, plot_info.plot_size
+    );
+    println!(
+        "Public key: {}",
+        plot_info.public_key.as_deref().unwrap_or("-")
+    );
+    println!(
+        "Farmer ID: {}",
+        plot_info.farmer_id.as_deref().unwrap_or("-")
+    );
+    if plot_info.commitments.is_empty() {
+        println!("Commitments: none");
+    } else {
+        println!("Commitments:");
+        for commitment in &plot_info.commitments {
+            println!(
+                "  {}: {:?}, {} bytes",
+                commitment.salt, commitment.status, commitment.size
+            );
+        }
+    }
+    if plot_info.issues.is_empty() {
+        println!("No issues found");
+    } else {
+        println!("Issues:");
+        for issue in &plot_info.issues {
+            println!("  {}", issue);
+        }
+    }
+
+    Ok(())
+}
+
+/// Collect information about plot in specified path by reading files directly, without opening
+/// the plot (so it is safe to use while farmer is running).
+pub fn plot_info(path: &Path) -> Result<PlotInfo, CommandError> {
+    let mut issues = Vec::new();
+
+    let plot_size = match fs::metadata(path.join("plot.bin")) {
+        Ok(metadata) => metadata.len(),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            issues.push("Plot file not found".to_string());
+            0
+        }
+        Err(error) => {
+            return Err(error.into());
+        }
+    };
+    if plot_size % PIECE_SIZE as u64 != 0 {
+        issues.push(format!(
+            "Plot size {} is not a multiple of piece size {}, last {} bytes are not used",
+            plot_size,
+            PIECE_SIZE,
+            plot_size % PIECE_SIZE as u64
+        ));
+    }
+
+    let (public_key, farmer_id) = match super::open_keypair(path) {
+        Ok(keypair) => (
+            Some(hex::encode(keypair.public.to_bytes())),
+            Some(hex::encode(crypto::hash_public_key(&keypair.public))),
+        ),
+        Err(error @ CommandError::IdentityNotFound)
+        | Err(error @ CommandError::InvalidIdentity(_)) => {
+            issues.push(error.to_string());
+            (None, None)
+        }
+        Err(error) => {
+            return Err(error);
+        }
+    };
+
+    match fs::metadata(path.join("genesis-piece.bin")) {
+        Ok(metadata) => {
+            if metadata.len() != PIECE_SIZE as u64 {
+                issues.push(CommandError::InvalidGenesisPiece.to_string());
+            }
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            issues.push(CommandError::GenesisPieceNotFound.to_string());
+        }
+        Err(error) => {
+            return Err(error.into());
+        }
+    }
+
+    let tags_path = path.join("plot-tags");
+    let statuses = match plot::read_commitment_statuses(path) {
+        Ok(statuses) => statuses.unwrap_or_default(),
+        Err(error) => {
+            issues.push(error.to_string());
+            Default::default()
+        }
+    };
+
+    let mut commitments = Vec::with_capacity(statuses.len());
+    for (salt, status) in statuses {
+        let salt = hex::encode(salt);
+        let commitment_path = tags_path.join(&salt);
+        let size = if commitment_path.is_dir() {
+            directory_size(&commitment_path)?
+        } else {
+            issues.push(format!("Commitment {} is missing on disk", salt));
+            0
+        };
+        if status == CommitmentStatus::InProgress {
+            issues.push(format!(
+                "Commitment {} is incomplete and will be removed on next start",
+                salt
+            ));
+        }
+
+        commitments.push(CommitmentInfo { salt, status, size });
+    }
+    commitments.sort_by(|a, b| a.salt.cmp(&b.salt));
+
+    if tags_path.is_dir() {
+        let mut orphaned = Vec::new();
+        for entry in fs::read_dir(&tags_path)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !commitments.iter().any(|commitment| commitment.salt == name) {
+                orphaned.push(name);
+            }
+        }
+        orphaned.sort();
+        for name in orphaned {
+            issues.push(format!(
+                "Directory {} is not a known commitment",
+                tags_path.join(name).display()
+            ));
+        }
+    }
+
+    Ok(PlotInfo {
+        piece_count: plot_size / PIECE_SIZE as u64,
+        plot_size,
+        public_key,
+        farmer_id,
+        commitments,
+        issues,
+    })
+}
+
+fn directory_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            directory_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_plot_info() {
+        let path = PathBuf::from("target").join("plot_info");
+        drop(fs::remove_dir_all(&path));
+        fs::create_dir_all(path.join("plot-tags").join("0102030405060708")).unwrap();
+        fs::create_dir_all(path.join("plot-tags").join("orphaned")).unwrap();
+
+        fs::write(path.join("plot.bin"), vec![0u8; PIECE_SIZE * 2 + 1]).unwrap();
+        fs::write(
+            path.join("plot-tags").join("0102030405060708").join("tags"),
+            [0u8; 10],
+        )
+        .unwrap();
+        fs::write(
+            path.join("plot-tags").join("metadata.json"),
+            r#"{"commitments":{"0102030405060708":"Created","0807060504030201":"InProgress"}}"#,
+        )
+        .unwrap();
+        let keypair = crypto::keypair_from_mnemonic(&crypto::generate_mnemonic()).unwrap();
+        fs::write(path.join("identity.bin"), keypair.to_bytes()).unwrap();
+
+        let plot_info = plot_info(&path).unwrap();
+        fs::remove_dir_all(&path).unwrap();
+
+        assert_eq!(plot_info.piece_count, 2);
+        assert_eq!(plot_info.plot_size, PIECE_SIZE as u64 * 2 + 1);
+        assert_eq!(
+            plot_info.public_key,
+            Some(hex::encode(keypair.public.to_bytes()))
+        );
+        assert_eq!(plot_info.commitments.len(), 2);
+        assert_eq!(plot_info.commitments[0].salt, "0102030405060708");
+        assert_eq!(plot_info.commitments[0].status, CommitmentStatus::Created);
+        assert_eq!(plot_info.commitments[0].size, 10);
+        assert_eq!(
+            plot_info.commitments[1].status,
+            CommitmentStatus::InProgress
+        );
+        // Not a multiple of piece size, no genesis piece, missing and incomplete commitment,
+        // orphaned directory
+        assert_eq!(plot_info.issues.len(), 5, "{:?}", plot_info.issues);
+    }
+}
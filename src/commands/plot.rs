@@ -1,87 +1,255 @@
-use crate::plot::Plot;
-use crate::{crypto, Piece, BATCH_SIZE, ENCODE_ROUNDS, PIECE_SIZE, PRIME_SIZE_BYTES};
+use crate::commands::CommandError;
+use crate::plot::{Plot, PlotError, PlotOptions};
+use crate::rpc::SlotInfo;
+use crate::{crypto, Piece, Salt, Tag, ENCODE_ROUNDS, PIECE_SIZE, PRIME_SIZE_BYTES};
 use futures::channel::{mpsc, oneshot};
-use futures::{SinkExt, StreamExt};
+use futures::{future, SinkExt, StreamExt};
 use indicatif::ProgressBar;
+use jsonrpsee::ws_client::traits::SubscriptionClient;
+use jsonrpsee::ws_client::v2::params::JsonRpcParams;
+use jsonrpsee::ws_client::{Subscription, WsClientBuilder};
 use log::{info, warn};
 use rayon::prelude::*;
 use schnorrkel::Keypair;
 use spartan_codec::Spartan;
+use std::collections::HashSet;
 use std::fs;
+use std::io::{self, Read};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Instant;
 
+/// Plot file is synced after this many bytes are written, such that dirty pages don't pile up
+const SYNC_INTERVAL: u64 = 1024 * 1024 * 1024;
+
+/// Options of plot command
+#[derive(Debug, Clone, Default)]
+pub struct PlottingOptions {
+    /// If specified, identity is derived from this BIP39 mnemonic deterministically, which allows
+    /// to restore the same identity (and re-create the same plot) later
+    pub mnemonic: Option<String>,
+    /// Write plot bypassing page cache, see [`PlotOptions::direct_io`]
+    pub direct_io: bool,
+    /// Salts to create commitments for in the same pass as encoding, such that farmer doesn't
+    /// need to read the whole plot back to create them
+    pub salts: Vec<Salt>,
+    /// If specified, current and next salt are fetched from the node at this WebSocket RPC
+    /// address and added to `salts`, such that farmer is productive right after plotting
+    pub ws_server: Option<String>,
+    /// Number of pieces encoded and written at once, see [`PlotOptions::batch_size`], default is
+    /// used if not specified
+    pub batch_size: Option<u64>,
+    /// Capacity of plot's request queues, see [`PlotOptions::queue_size`], default is used if not
+    /// specified
+    pub queue_size: Option<usize>,
+}
+
 /// Create a new plot with specified genesis piece and piece count.
-pub(crate) async fn plot(
+///
+/// Disk space for the whole plot is reserved upfront. Encoding of the next batch of pieces
+/// overlaps with writing of the previous one, tags for specified salts are computed right after
+/// encoding and written once encodings are written. Plotting is aborted on the first failed
+/// write, leaving commitments unfinished.
+pub async fn plot(
     path: PathBuf,
     genesis_piece: Piece,
     piece_count: u64,
-) -> Result<(), Box<dyn std::error::Error>> {
+    options: PlottingOptions,
+) -> Result<(), CommandError> {
+    let PlottingOptions {
+        mnemonic,
+        direct_io,
+        mut salts,
+        ws_server,
+        batch_size,
+        queue_size,
+    } = options;
+    if let Some(ws_server) = ws_server {
+        salts.extend(fetch_salts(&ws_server).await?);
+    }
+    salts.sort_unstable();
+    salts.dedup();
+
+    fs::create_dir_all(&path)?;
+
     let identity_file = path.join("identity.bin");
     let keypair = if identity_file.exists() {
         info!("Opening existing keypair");
-        Keypair::from_bytes(&fs::read(identity_file)?).map_err(|error| error.to_string())?
+        let keypair = Keypair::from_bytes(&fs::read(identity_file)?)
+            .map_err(CommandError::InvalidIdentity)?;
+
+        if let Some(mnemonic) = mnemonic {
+            if crypto::keypair_from_mnemonic(&mnemonic)?.public != keypair.public {
+                return Err(CommandError::IdentityMismatch);
+            }
+        }
+
+        keypair
     } else {
-        info!("Generating new keypair");
-        let keypair = Keypair::generate();
+        let mnemonic = match mnemonic {
+            Some(mnemonic) => {
+                info!("Deriving keypair from provided mnemonic");
+                mnemonic
+            }
+            None => {
+                info!("Generating new keypair");
+                let mnemonic = crypto::generate_mnemonic();
+                warn!(
+                    "Write down the following mnemonic, it can be used to restore identity \
+                    with `--mnemonic` option:\n{}",
+                    mnemonic
+                );
+                mnemonic
+            }
+        };
+        let keypair = crypto::keypair_from_mnemonic(&mnemonic)?;
         fs::write(identity_file, keypair.to_bytes())?;
         keypair
     };
 
-    let plot = Plot::open_or_create(&path.into()).await?;
     let public_key_hash = crypto::hash_public_key(&keypair.public);
     let spartan: Arc<Spartan<PRIME_SIZE_BYTES, PIECE_SIZE>> =
         Arc::new(Spartan::<PRIME_SIZE_BYTES, PIECE_SIZE>::new(genesis_piece));
 
+    let genesis_piece_file = path.join("genesis-piece.bin");
+    if genesis_piece_file.exists() {
+        if fs::read(&genesis_piece_file)? != genesis_piece {
+            return Err(CommandError::GenesisPieceMismatch);
+        }
+    } else {
+        // Plots created before genesis piece was stored don't have it, in which case seed must
+        // match existing plot, otherwise farmer would reject all of its solutions
+        let mut first_encoding: Piece = [0u8; PIECE_SIZE];
+        match fs::File::open(path.join("plot.bin")) {
+            Ok(mut plot_file) => {
+                if plot_file.metadata()?.len() >= PIECE_SIZE as u64 {
+                    plot_file.read_exact(&mut first_encoding)?;
+                    if !spartan.is_valid(first_encoding, public_key_hash, 0, ENCODE_ROUNDS) {
+                        return Err(CommandError::GenesisPieceMismatch);
+                    }
+                }
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+            Err(error) => {
+                return Err(error.into());
+            }
+        }
+        // Stored for farmer to be able to verify solutions before submitting them
+        fs::write(genesis_piece_file, genesis_piece)?;
+    }
+
+    let default_options = PlotOptions::default();
+    let plot_options = PlotOptions {
+        direct_io,
+        sync_interval: Some(SYNC_INTERVAL),
+        preallocate: Some(piece_count),
+        batch_size: batch_size.unwrap_or(default_options.batch_size).max(1),
+        queue_size: queue_size.unwrap_or(default_options.queue_size),
+        ..default_options
+    };
+    let batch_size = plot_options.batch_size;
+    let plot = Plot::open_with_options(&path.into(), plot_options).await?;
+
     if plot.is_empty().await {
         let plotting_fut = {
             let plot = plot.clone();
+            let salts = salts.clone();
 
             async move {
                 let (mut batch_sender, mut batch_receiver) = mpsc::channel(1);
 
-                std::thread::spawn(move || {
-                    let bar = ProgressBar::new(piece_count);
-
-                    for batch_start in (0..piece_count).step_by(BATCH_SIZE as usize) {
-                        let batch_end = (batch_start + BATCH_SIZE).min(piece_count);
-                        let encoded_batch: Vec<Piece> = (batch_start..batch_end)
-                            .into_par_iter()
-                            .map(|index| {
-                                let encoding =
-                                    spartan.encode(public_key_hash, index, ENCODE_ROUNDS);
-
-                                bar.inc(1);
-
-                                encoding
-                            })
-                            .collect();
-
-                        if futures::executor::block_on(
-                            batch_sender.send((batch_start, encoded_batch)),
-                        )
-                        .is_err()
-                        {
-                            return;
+                std::thread::spawn({
+                    let salts = salts.clone();
+
+                    move || {
+                        let bar = ProgressBar::new(piece_count);
+
+                        for batch_start in (0..piece_count).step_by(batch_size as usize) {
+                            let batch_end = (batch_start + batch_size).min(piece_count);
+                            let encoded_batch: Vec<Piece> = (batch_start..batch_end)
+                                .into_par_iter()
+                                .map(|index| {
+                                    let encoding =
+                                        spartan.encode(public_key_hash, index, ENCODE_ROUNDS);
+
+                                    bar.inc(1);
+
+                                    encoding
+                                })
+                                .collect();
+                            let tags: Vec<Vec<Tag>> = salts
+                                .iter()
+                                .map(|salt| {
+                                    encoded_batch
+                                        .par_iter()
+                                        .map(|encoding| crypto::create_tag(encoding, salt))
+                                        .collect()
+                                })
+                                .collect();
+
+                            if futures::executor::block_on(batch_sender.send((
+                                batch_start,
+                                encoded_batch,
+                                tags,
+                            )))
+                            .is_err()
+                            {
+                                return;
+                            }
                         }
-                    }
 
-                    bar.finish();
+                        bar.finish();
+                    }
                 });
-                while let Some((batch_start, encoded_batch)) = batch_receiver.next().await {
-                    let result = plot.write_many(encoded_batch, batch_start).await;
 
-                    if let Err(error) = result {
-                        warn!("{}", error);
+                // Commitments for these salts are left unfinished and will be removed on next
+                // start, farmer will re-create them
+                let mut failed_salts = HashSet::new();
+                while let Some((batch_start, encoded_batch, tags)) = batch_receiver.next().await {
+                    // Tags must never be written for pieces that are not in the plot, otherwise
+                    // commitment would be finished over holes in the plot
+                    plot.write_many(encoded_batch, batch_start).await?;
+
+                    let tags_results = future::join_all(
+                        salts
+                            .iter()
+                            .zip(tags)
+                            .filter(|(salt, _tags)| !failed_salts.contains(*salt))
+                            .map(|(&salt, tags)| {
+                                let plot = &plot;
+
+                                async move {
+                                    (salt, plot.write_tags(salt, batch_start, tags).await)
+                                }
+                            }),
+                    )
+                    .await;
+
+                    for (salt, result) in tags_results {
+                        if let Err(error) = result {
+                            warn!(
+                                "Failed to write tags for salt {}: {}",
+                                hex::encode(salt),
+                                error
+                            );
+                            failed_salts.insert(salt);
+                        }
                     }
                 }
+
+                Ok::<_, PlotError>(failed_salts)
             }
         };
 
         let plot_time = Instant::now();
 
         info!("Slowly plotting {} pieces...", piece_count);
+        if !salts.is_empty() {
+            info!(
+                "Creating commitments for {} salts while plotting",
+                salts.len()
+            );
+        }
 
         info!(
             r#"
@@ -99,7 +267,12 @@ pub(crate) async fn plot(
         "#
         );
 
-        plotting_fut.await;
+        let failed_salts = plotting_fut.await?;
+        for salt in salts {
+            if !failed_salts.contains(&salt) {
+                plot.finish_commitment(salt).await?;
+            }
+        }
 
         let (tx, rx) = oneshot::channel();
 
@@ -111,7 +284,7 @@ pub(crate) async fn plot(
 
         info!("Finishing writing to disk...");
 
-        rx.await?;
+        rx.await.map_err(|_| PlotError::Closed)?;
 
         let total_plot_time = plot_time.elapsed();
         let average_plot_time =
@@ -133,6 +306,11 @@ pub(crate) async fn plot(
         info!("Using existing plot...");
         info!("NOTE: Use erase-plot command if you want to re-plot before running plot again.");
 
+        for salt in salts {
+            info!("Creating commitment for salt {}", hex::encode(salt));
+            plot.create_commitment(salt).await?;
+        }
+
         let (tx, rx) = oneshot::channel();
 
         let _handler = plot.on_close(move || {
@@ -141,8 +319,35 @@ pub(crate) async fn plot(
 
         drop(plot);
 
-        rx.await?;
+        rx.await.map_err(|_| PlotError::Closed)?;
     }
 
     Ok(())
 }
+
+/// Fetch current and next (if announced) salt from the first slot notification of the node
+async fn fetch_salts(ws_server: &str) -> Result<Vec<Salt>, CommandError> {
+    info!("Connecting to RPC server to fetch salts");
+    let client = WsClientBuilder::default().build(ws_server).await?;
+    let mut sub: Subscription<SlotInfo> = client
+        .subscribe(
+            "poc_subscribeSlotInfo",
+            JsonRpcParams::NoParams,
+            "poc_unsubscribeSlotInfo",
+        )
+        .await?;
+    let slot_info = sub.next().await.ok_or(CommandError::SlotInfoNotReceived)?;
+
+    info!(
+        "Fetched salt {} and next salt {}",
+        hex::encode(slot_info.salt),
+        slot_info
+            .next_salt
+            .map(hex::encode)
+            .unwrap_or_else(|| "(not announced)".to_string())
+    );
+
+    Ok(std::iter::once(slot_info.salt)
+        .chain(slot_info.next_salt)
+        .collect())
+}
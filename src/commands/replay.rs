@@ -0,0 +1,157 @@
+use crate::commands::CommandError;
+use crate::farming::{SlotHandler, SlotRecord};
+use crate::metrics::Metrics;
+use crate::plot::{Plot, PlotError};
+use crate::rpc::Solution;
+use crate::verification;
+use futures::channel::oneshot;
+use log::{info, warn};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+/// Replay slots recorded by farm command through the farming logic against plot in specified path
+/// and show for every slot whether solution was found and how it differs from the one that was
+/// submitted.
+///
+/// Existing commitments are preserved, commitments created for recorded salts are removed at the
+/// end.
+pub async fn replay(path: PathBuf, record_file: PathBuf) -> Result<(), CommandError> {
+    let keypair = super::open_keypair(&path)?;
+    let public_key = keypair.public;
+    let spartan = super::open_spartan(&path)?;
+    // Separate instance for verifying submitted solutions
+    let verification_spartan = super::open_spartan(&path)?;
+
+    info!("Opening plot");
+    let plot = Plot::open_or_create(&path.into()).await?;
+
+    if plot.is_empty().await {
+        return Err(CommandError::PlotEmpty);
+    }
+
+    let records = BufReader::new(File::open(record_file)?);
+    let existing_salts = plot
+        .commitment_statuses()
+        .keys()
+        .copied()
+        .collect::<Vec<_>>();
+    let mut slot_handler = SlotHandler::new(plot.clone(), keypair, spartan, Metrics::default());
+    slot_handler.preserve_commitments(existing_salts.iter().copied());
+
+    let mut slots = 0_u64;
+    let mut same = 0_u64;
+    let mut missed = 0_u64;
+    let mut not_found = 0_u64;
+    let mut different = 0_u64;
+
+    for (line, record) in records.lines().enumerate() {
+        let record = record?;
+        if record.trim().is_empty() {
+            continue;
+        }
+        let SlotRecord {
+            slot_info,
+            solution: submitted,
+        } = serde_json::from_str(&record).map_err(|error| CommandError::InvalidRecord {
+            line: line + 1,
+            error,
+        })?;
+
+        // Commitments are created upfront (farmer does this in background) so that results don't
+        // depend on how fast replay is
+        plot.create_commitment(slot_info.salt).await?;
+        if let Some(next_salt) = slot_info.next_salt {
+            plot.create_commitment(next_salt).await?;
+        }
+
+        let found = slot_handler.handle_slot(&slot_info).await?;
+        slots += 1;
+
+        let slot_number = slot_info.slot_number;
+        match (&submitted, &found) {
+            (None, None) => {
+                same += 1;
+                info!("Slot {}: no solution", slot_number);
+            }
+            (None, Some(found)) => {
+                missed += 1;
+                warn!(
+                    "Slot {}: solution {} was not submitted",
+                    slot_number,
+                    describe(found)
+                );
+            }
+            (Some(submitted), None) => {
+                not_found += 1;
+                warn!(
+                    "Slot {}: submitted solution {} was not found",
+                    slot_number,
+                    describe(submitted)
+                );
+            }
+            (Some(submitted), Some(found)) => {
+                if submitted.nonce == found.nonce && submitted.tag == found.tag {
+                    same += 1;
+                    info!(
+                        "Slot {}: same solution {}",
+                        slot_number,
+                        describe(submitted)
+                    );
+                } else {
+                    different += 1;
+                    warn!(
+                        "Slot {}: submitted solution {} differs from found solution {}",
+                        slot_number,
+                        describe(submitted),
+                        describe(found)
+                    );
+                }
+            }
+        }
+
+        if let Some(submitted) = &submitted {
+            if let Err(error) = verification::verify_solution(
+                submitted,
+                &slot_info,
+                &public_key,
+                &verification_spartan,
+            ) {
+                warn!(
+                    "Slot {}: submitted solution is invalid: {}",
+                    slot_number, error
+                );
+            }
+        }
+    }
+
+    drop(slot_handler);
+    // Plot is left with the same commitments it had before replay
+    plot.retain_commitments(existing_salts).await?;
+
+    info!(
+        "Replayed {} slots: {} same, {} solutions not submitted, {} submitted solutions not \
+        found, {} different solutions",
+        slots, same, missed, not_found, different
+    );
+
+    let (tx, rx) = oneshot::channel();
+
+    let _handler = plot.on_close(move || {
+        let _ = tx.send(());
+    });
+
+    drop(plot);
+
+    rx.await.map_err(|_| PlotError::Closed)?;
+
+    Ok(())
+}
+
+fn describe(solution: &Solution) -> String {
+    format!(
+        "(nonce {}, tag {})",
+        solution.nonce,
+        hex::encode(solution.tag)
+    )
+}
@@ -0,0 +1,282 @@
+use crate::commands::CommandError;
+use crate::farming::{CommitmentEvent, SlotHandler};
+use crate::metrics::Metrics;
+use crate::plot::{CommitmentStatus, Plot, PlotError};
+use crate::rpc::{SlotInfo, SlotNumber};
+use crate::{Salt, PRIME_SIZE_BYTES};
+use async_std::task;
+use futures::channel::oneshot;
+use log::{debug, info};
+use ring::digest;
+use std::convert::TryInto;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Options of synthetic slots used for simulation
+#[derive(Debug, Clone)]
+pub struct SimulationOptions {
+    /// Number of slots to simulate
+    pub slots: u64,
+    /// Duration of one slot
+    pub slot_duration: Duration,
+    /// Number of slots after which salt changes
+    pub salt_rotation: u64,
+    /// Number of slots before salt change during which next salt is announced, `0` means next salt
+    /// is never announced and each salt change is unexpected
+    pub next_salt_announcement: u64,
+    /// Solution range, if not specified it is chosen such that one tag falls into solution range
+    /// on average
+    pub solution_range: Option<u64>,
+}
+
+/// Deterministic generator of synthetic slots
+struct SlotGenerator {
+    salt_rotation: u64,
+    next_salt_announcement: u64,
+    solution_range: u64,
+}
+
+impl SlotGenerator {
+    fn slot_info(&self, slot_number: SlotNumber) -> SlotInfo {
+        let eon = slot_number / self.salt_rotation;
+        let slots_until_next_eon = self.salt_rotation - slot_number % self.salt_rotation;
+        let next_salt = if slots_until_next_eon <= self.next_salt_announcement {
+            Some(Self::derive(b"salt", eon + 1))
+        } else {
+            None
+        };
+
+        SlotInfo {
+            slot_number,
+            challenge: Self::derive(b"challenge", slot_number),
+            salt: Self::derive(b"salt", eon),
+            next_salt,
+            solution_range: self.solution_range,
+        }
+    }
+
+    fn derive(domain: &[u8], number: u64) -> Salt {
+        digest::digest(&digest::SHA256, &[domain, &number.to_le_bytes()].concat()).as_ref()
+            [..PRIME_SIZE_BYTES]
+            .try_into()
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Default)]
+struct CommitmentStats {
+    started: u64,
+    finished: u64,
+    failed: u64,
+    removed: u64,
+    durations: Vec<Duration>,
+}
+
+/// Simulate farming using plot in specified path with locally generated slots instead of a node,
+/// reports win rate, solution latency and recommitment behavior.
+///
+/// Existing commitments are preserved, commitments for synthetic salts are removed at the end.
+pub async fn simulate(path: PathBuf, options: SimulationOptions) -> Result<(), CommandError> {
+    let keypair = super::open_keypair(&path)?;
+    let spartan = super::open_spartan(&path)?;
+
+    info!("Opening plot");
+    let plot = Plot::open_or_create(&path.into()).await?;
+
+    if plot.is_empty().await {
+        return Err(CommandError::PlotEmpty);
+    }
+
+    let piece_count = plot.piece_count();
+    let generator = SlotGenerator {
+        salt_rotation: options.salt_rotation.max(1),
+        next_salt_announcement: options.next_salt_announcement,
+        solution_range: options
+            .solution_range
+            .unwrap_or_else(|| u64::MAX / piece_count),
+    };
+
+    info!(
+        "Simulating {} slots of {} ms each with solution range {}",
+        options.slots,
+        options.slot_duration.as_millis(),
+        generator.solution_range
+    );
+
+    let existing_salts = plot
+        .commitment_statuses()
+        .keys()
+        .copied()
+        .collect::<Vec<_>>();
+    let mut slot_handler = SlotHandler::new(plot.clone(), keypair, spartan, Metrics::default());
+    slot_handler.preserve_commitments(existing_salts.iter().copied());
+
+    let commitment_stats = Arc::new(Mutex::new(CommitmentStats::default()));
+    let _handler = slot_handler.on_commitment({
+        let commitment_stats = Arc::clone(&commitment_stats);
+
+        move |event| {
+            let mut commitment_stats = commitment_stats.lock().unwrap();
+            match event {
+                CommitmentEvent::Started { .. } => {
+                    commitment_stats.started += 1;
+                }
+                CommitmentEvent::Finished { duration, .. } => {
+                    commitment_stats.finished += 1;
+                    commitment_stats.durations.push(*duration);
+                }
+                CommitmentEvent::Failed { .. } => {
+                    commitment_stats.failed += 1;
+                }
+                CommitmentEvent::Removed { .. } => {
+                    commitment_stats.removed += 1;
+                }
+            }
+        }
+    });
+
+    let mut wins = 0_u64;
+    let mut late_slots = 0_u64;
+    let mut slots_without_commitment = 0_u64;
+    let mut latencies = Vec::with_capacity(options.slots as usize);
+
+    for slot_number in 0..options.slots {
+        let slot_info = generator.slot_info(slot_number);
+        debug!("New slot: {:?}", slot_info);
+
+        let started = Instant::now();
+        let solution = slot_handler.handle_slot(&slot_info).await?;
+        let latency = started.elapsed();
+
+        if plot.commitment_statuses().get(&slot_info.salt) != Some(&CommitmentStatus::Created) {
+            slots_without_commitment += 1;
+        }
+        if latency > options.slot_duration {
+            late_slots += 1;
+        }
+        if solution.is_some() {
+            wins += 1;
+        }
+        latencies.push(latency);
+
+        info!(
+            "Slot {}: solution {} in {:.3} ms",
+            slot_number,
+            if solution.is_some() {
+                "found"
+            } else {
+                "not found"
+            },
+            latency.as_secs_f64() * 1000_f64
+        );
+
+        if slot_number + 1 < options.slots {
+            if let Some(remaining) = options.slot_duration.checked_sub(latency) {
+                task::sleep(remaining).await;
+            }
+        }
+    }
+
+    drop(slot_handler);
+
+    latencies.sort_unstable();
+    let percentile = |percentile: usize| -> f64 {
+        latencies
+            .get((latencies.len() * percentile / 100).min(latencies.len().saturating_sub(1)))
+            .map(|latency| latency.as_secs_f64() * 1000_f64)
+            .unwrap_or_default()
+    };
+
+    info!("Simulation results:");
+    info!(
+        "Win rate: {}/{} ({:.2}%)",
+        wins,
+        options.slots,
+        wins as f64 * 100_f64 / options.slots.max(1) as f64
+    );
+    info!(
+        "Solution latency: p50 {:.3} ms, p90 {:.3} ms, p99 {:.3} ms, max {:.3} ms",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        percentile(100)
+    );
+    info!("Slots that took longer than slot duration: {}", late_slots);
+    info!(
+        "Slots handled before commitment for current salt was created: {}",
+        slots_without_commitment
+    );
+    {
+        let commitment_stats = commitment_stats.lock().unwrap();
+        let average_duration = if commitment_stats.durations.is_empty() {
+            0_f32
+        } else {
+            commitment_stats
+                .durations
+                .iter()
+                .sum::<Duration>()
+                .as_secs_f32()
+                / commitment_stats.durations.len() as f32
+        };
+        info!(
+            "Recommitments: {} started, {} finished (average {:.3} seconds), {} failed, {} \
+            commitments removed",
+            commitment_stats.started,
+            commitment_stats.finished,
+            average_duration,
+            commitment_stats.failed,
+            commitment_stats.removed
+        );
+    }
+
+    // Plot is left with the same commitments it had before simulation, recommitments that are
+    // still in progress are aborted and clean up after themselves
+    plot.retain_commitments(existing_salts).await?;
+    while {
+        let commitment_stats = commitment_stats.lock().unwrap();
+        commitment_stats.started > commitment_stats.finished + commitment_stats.failed
+    } {
+        task::sleep(Duration::from_millis(10)).await;
+    }
+
+    let (tx, rx) = oneshot::channel();
+
+    let _handler = plot.on_close(move || {
+        let _ = tx.send(());
+    });
+
+    drop(plot);
+
+    rx.await.map_err(|_| PlotError::Closed)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_generator() {
+        let generator = SlotGenerator {
+            salt_rotation: 10,
+            next_salt_announcement: 3,
+            solution_range: 100,
+        };
+
+        let first_salt = generator.slot_info(0).salt;
+        assert_eq!(generator.slot_info(6).salt, first_salt);
+        assert_eq!(generator.slot_info(6).next_salt, None);
+        assert_ne!(
+            generator.slot_info(0).challenge,
+            generator.slot_info(1).challenge
+        );
+
+        let next_salt = generator.slot_info(7).next_salt.unwrap();
+        assert_ne!(next_salt, first_salt);
+        assert_eq!(generator.slot_info(9).next_salt, Some(next_salt));
+        assert_eq!(generator.slot_info(10).salt, next_salt);
+        assert_eq!(generator.slot_info(10).next_salt, None);
+    }
+}
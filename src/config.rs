@@ -0,0 +1,218 @@
+//! Configuration file of the farmer.
+//!
+//! Configuration is stored in TOML format, all fields are optional:
+//! ```toml
+//! data_dir = "/var/spartan"
+//!
+//! [node]
+//! ws_server = "ws://127.0.0.1:9944"
+//! reconnect_attempts = 10
+//!
+//! [plot]
+//! mmap = false
+//! tag_index = false
+//! direct_io = false
+//! batch_size = 4096
+//! queue_size = 100
+//!
+//! [threads]
+//! encoding = 4
+//!
+//! [log]
+//! filter = "info"
+//!
+//! [metrics]
+//! address = "127.0.0.1:9616"
+//!
+//! [api]
+//! address = "127.0.0.1:9617"
+//! ```
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Name of configuration file that is looked up in data directory if no explicit path is given
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Errors that happen when loading configuration
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    /// Failed to read configuration file
+    #[error("Failed to read config file {path}: {error}")]
+    Read {
+        /// Path to configuration file
+        path: PathBuf,
+        /// Low-level error
+        error: io::Error,
+    },
+    /// Configuration file is not valid
+    #[error("Invalid config file {path}: {error}")]
+    Parse {
+        /// Path to configuration file
+        path: PathBuf,
+        /// Low-level error
+        error: toml::de::Error,
+    },
+}
+
+/// Farmer configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directory with plot, commitments and identity
+    pub data_dir: Option<PathBuf>,
+    /// Node to farm against
+    pub node: NodeConfig,
+    /// Plot
+    pub plot: PlotConfig,
+    /// Thread limits
+    pub threads: ThreadsConfig,
+    /// Logging
+    pub log: LogConfig,
+    /// Prometheus metrics
+    pub metrics: MetricsConfig,
+    /// Local status/control API
+    pub api: ApiConfig,
+}
+
+/// Node configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// WebSocket RPC address of the node
+    pub ws_server: String,
+    /// Number of attempts to reconnect after connection to the node is lost before farmer gives
+    /// up, zero disables reconnection
+    pub reconnect_attempts: u32,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            ws_server: "ws://127.0.0.1:9944".to_string(),
+            reconnect_attempts: 10,
+        }
+    }
+}
+
+/// Plot configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlotConfig {
+    /// Read plot through memory mapping while farming, recommended for plots on SSD
+    pub mmap: bool,
+    /// Keep in-memory index of tags while farming, recommended for very large plots
+    pub tag_index: bool,
+    /// Write plot bypassing page cache while plotting, recommended for large plots
+    pub direct_io: bool,
+    /// Number of pieces processed at once while plotting and creating commitments
+    pub batch_size: Option<u64>,
+    /// Capacity of plot's request queue of each priority
+    pub queue_size: Option<usize>,
+}
+
+/// Thread limits configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThreadsConfig {
+    /// Number of threads used for encoding and tagging, number of CPU cores if not specified
+    pub encoding: Option<usize>,
+}
+
+/// Logging configuration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Log filter in `env_logger` format, `RUST_LOG` environment variable takes precedence
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info".to_string(),
+        }
+    }
+}
+
+/// Prometheus metrics configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address to serve metrics on, metrics are not served if not specified
+    pub address: Option<SocketAddr>,
+}
+
+/// Local status/control API configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Address to serve API on, API is not served if not specified
+    pub address: Option<SocketAddr>,
+}
+
+impl Config {
+    /// Load configuration from TOML file
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|error| ConfigError::Read {
+            path: path.to_path_buf(),
+            error,
+        })?;
+
+        toml::from_str(&contents).map_err(|error| ConfigError::Parse {
+            path: path.to_path_buf(),
+            error,
+        })
+    }
+
+    /// Load configuration from TOML file or return default configuration if file doesn't exist
+    pub fn load_or_default(path: &Path) -> Result<Self, ConfigError> {
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    /// Encode configuration in TOML format
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [node]
+            ws_server = "ws://example.com:9944"
+
+            [metrics]
+            address = "127.0.0.1:9616"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.node.ws_server, "ws://example.com:9944");
+        assert_eq!(
+            config.node.reconnect_attempts,
+            NodeConfig::default().reconnect_attempts
+        );
+        assert_eq!(
+            config.metrics.address,
+            Some("127.0.0.1:9616".parse().unwrap())
+        );
+        assert_eq!(config.log, LogConfig::default());
+        assert_eq!(config.api.address, None);
+
+        assert_eq!(toml::from_str::<Config>(&config.to_toml()).unwrap(), config);
+        assert!(toml::from_str::<Config>("[node]\nws = \"typo\"").is_err());
+    }
+}
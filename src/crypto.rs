@@ -1,10 +1,22 @@
+//! Cryptographic primitives used for plotting and farming
 use crate::{Piece, Tag, PIECE_SIZE, PRIME_SIZE_BYTES};
+use bip39::{Language, Mnemonic, MnemonicType, Seed};
 use ring::{digest, hmac};
-use schnorrkel::PublicKey;
+use schnorrkel::{ExpansionMode, Keypair, MiniSecretKey, PublicKey, MINI_SECRET_KEY_LENGTH};
 use std::convert::TryInto;
 use std::io::Write;
+use thiserror::Error;
 
-pub(crate) fn genesis_piece_from_seed(seed: &str) -> Piece {
+/// Mnemonic-related errors
+#[derive(Debug, Error)]
+pub enum MnemonicError {
+    /// Mnemonic phrase is not valid BIP39 phrase
+    #[error("Invalid mnemonic phrase: {0}")]
+    InvalidPhrase(String),
+}
+
+/// Derives genesis piece from a short seed
+pub fn genesis_piece_from_seed(seed: &str) -> Piece {
     // This is not efficient, but it also doesn't matter as it is called just once
     let mut piece = [0u8; PIECE_SIZE];
     let mut input = seed.as_bytes().to_vec();
@@ -15,16 +27,71 @@ pub(crate) fn genesis_piece_from_seed(seed: &str) -> Piece {
     piece
 }
 
-pub(crate) fn hash_public_key(public_key: &PublicKey) -> [u8; PRIME_SIZE_BYTES] {
+/// Hashes farmer's public key into farmer ID, which is used as encoding key
+pub fn hash_public_key(public_key: &PublicKey) -> [u8; PRIME_SIZE_BYTES] {
     let mut array = [0u8; PRIME_SIZE_BYTES];
     let hash = digest::digest(&digest::SHA256, public_key.as_ref());
     array.copy_from_slice(&hash.as_ref()[..PRIME_SIZE_BYTES]);
     array
 }
 
-pub(crate) fn create_tag(encoding: &[u8], salt: &[u8]) -> Tag {
+/// Creates tag (commitment) for encoding as `hmac(encoding, salt)` truncated to tag size
+pub fn create_tag(encoding: &[u8], salt: &[u8]) -> Tag {
     let key = hmac::Key::new(hmac::HMAC_SHA256, salt);
     hmac::sign(&key, encoding).as_ref()[0..8]
         .try_into()
         .unwrap()
 }
+
+/// Derives farmer-specific local challenge as `hash(global_challenge || farmer_id)`
+pub fn derive_local_challenge(global_challenge: &[u8], farmer_id: &[u8]) -> [u8; 8] {
+    digest::digest(&digest::SHA256, &{
+        let mut data = Vec::with_capacity(global_challenge.len() + farmer_id.len());
+        data.extend_from_slice(global_challenge);
+        data.extend_from_slice(farmer_id);
+        data
+    })
+    .as_ref()[..8]
+        .try_into()
+        .unwrap()
+}
+
+/// Generates new random BIP39 mnemonic phrase that can be used to derive a keypair with
+/// [`keypair_from_mnemonic`]
+pub fn generate_mnemonic() -> String {
+    Mnemonic::new(MnemonicType::Words24, Language::English).into_phrase()
+}
+
+/// Derives keypair from BIP39 mnemonic phrase, the same phrase always results in the same keypair
+pub fn keypair_from_mnemonic(phrase: &str) -> Result<Keypair, MnemonicError> {
+    let mnemonic = Mnemonic::from_phrase(phrase, Language::English)
+        .map_err(|error| MnemonicError::InvalidPhrase(error.to_string()))?;
+    let seed = Seed::new(&mnemonic, "");
+    let mini_secret_key = MiniSecretKey::from_bytes(&seed.as_bytes()[..MINI_SECRET_KEY_LENGTH])
+        .expect("Seed is always longer than mini secret key; qed");
+
+    Ok(mini_secret_key.expand_to_keypair(ExpansionMode::Ed25519))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keypair_from_mnemonic() {
+        let phrase = generate_mnemonic();
+
+        let keypair = keypair_from_mnemonic(&phrase).unwrap();
+        let same_keypair = keypair_from_mnemonic(&phrase).unwrap();
+        assert_eq!(keypair.public, same_keypair.public);
+        assert_eq!(
+            keypair.secret.to_bytes()[..],
+            same_keypair.secret.to_bytes()[..]
+        );
+
+        let other_keypair = keypair_from_mnemonic(&generate_mnemonic()).unwrap();
+        assert_ne!(keypair.public, other_keypair.public);
+
+        assert!(keypair_from_mnemonic("not a valid mnemonic phrase").is_err());
+    }
+}
@@ -0,0 +1,456 @@
+//! Slot handling logic shared by farming against a node and offline simulation
+use crate::metrics::Metrics;
+use crate::plot::{Plot, PlotError};
+use crate::rpc::{SlotInfo, Solution};
+use crate::{crypto, verification, Salt, PIECE_SIZE, PRIME_SIZE_BYTES, SIGNING_CONTEXT};
+use async_std::task;
+use event_listener_primitives::{Bag, HandlerId};
+use log::{debug, error, info, trace, warn};
+use schnorrkel::Keypair;
+use serde::{Deserialize, Serialize};
+use spartan_codec::Spartan;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Events related to commitments that happen as a result of salt changes
+#[derive(Debug, Copy, Clone)]
+pub enum CommitmentEvent {
+    /// Commitment creation for salt has started in background
+    Started {
+        /// Salt
+        salt: Salt,
+    },
+    /// Commitment creation for salt has finished successfully
+    Finished {
+        /// Salt
+        salt: Salt,
+        /// How long did it take to create commitment
+        duration: Duration,
+    },
+    /// Commitment creation for salt has failed
+    Failed {
+        /// Salt
+        salt: Salt,
+    },
+    /// Commitment for salt was removed since salt is out of date
+    Removed {
+        /// Salt
+        salt: Salt,
+    },
+}
+
+/// Slot received from the node along with solution that was proposed for it, recorded by farmer
+/// one per line in JSON format for later replay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotRecord {
+    /// Slot info as received from the node
+    pub slot_info: SlotInfo,
+    /// Solution that was proposed to the node
+    pub solution: Option<Solution>,
+}
+
+#[derive(Default)]
+struct Handlers {
+    commitment: Bag<Box<dyn Fn(&CommitmentEvent) + Send + Sync>>,
+}
+
+#[derive(Default)]
+struct ControlState {
+    current_salt: Option<Salt>,
+    next_salt: Option<Salt>,
+    recommitment_paused: bool,
+    /// Salts for which commitment creation was requested while recommitment was paused
+    deferred_commitments: Vec<Salt>,
+}
+
+/// Handle for inspecting and controlling [`SlotHandler`] from other tasks, cheap to clone
+#[derive(Clone)]
+pub struct SlotHandlerControl {
+    plot: Plot,
+    state: Arc<Mutex<ControlState>>,
+    handlers: Arc<Handlers>,
+}
+
+impl SlotHandlerControl {
+    /// Current salt as of the last handled slot
+    pub fn current_salt(&self) -> Option<Salt> {
+        self.state.lock().unwrap().current_salt
+    }
+
+    /// Next salt as of the last handled slot
+    pub fn next_salt(&self) -> Option<Salt> {
+        self.state.lock().unwrap().next_salt
+    }
+
+    /// Whether recommitment on salt changes is paused
+    pub fn is_recommitment_paused(&self) -> bool {
+        self.state.lock().unwrap().recommitment_paused
+    }
+
+    /// Pause recommitment on salt changes, commitments that would have been created in the
+    /// meantime are deferred until recommitment is resumed
+    pub fn pause_recommitment(&self) {
+        info!("Pausing recommitment");
+        self.state.lock().unwrap().recommitment_paused = true;
+    }
+
+    /// Resume recommitment on salt changes and create deferred commitments for salts that are
+    /// still relevant
+    pub fn resume_recommitment(&self) {
+        info!("Resuming recommitment");
+        let deferred_commitments = {
+            let mut state = self.state.lock().unwrap();
+            state.recommitment_paused = false;
+            let (current_salt, next_salt) = (state.current_salt, state.next_salt);
+            state
+                .deferred_commitments
+                .drain(..)
+                .filter(|&salt| Some(salt) == current_salt || Some(salt) == next_salt)
+                .collect::<Vec<_>>()
+        };
+
+        for salt in deferred_commitments {
+            self.spawn_commitment_creation(salt);
+        }
+    }
+
+    /// Create commitment for specified salt in background, even if recommitment is paused
+    pub fn force_commitment(&self, salt: Salt) {
+        info!("Forcing commitment for {}", hex::encode(salt));
+        self.spawn_commitment_creation(salt);
+    }
+
+    /// Create commitment in background unless recommitment is paused, in which case it is
+    /// deferred
+    fn create_commitment(&self, salt: Salt) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.recommitment_paused {
+                info!(
+                    "Recommitment is paused, deferring commitment for {}",
+                    hex::encode(salt)
+                );
+                if !state.deferred_commitments.contains(&salt) {
+                    state.deferred_commitments.push(salt);
+                }
+                return;
+            }
+        }
+
+        self.spawn_commitment_creation(salt);
+    }
+
+    fn spawn_commitment_creation(&self, salt: Salt) {
+        let plot = self.plot.clone();
+        let handlers = Arc::clone(&self.handlers);
+
+        task::spawn(async move {
+            let started = Instant::now();
+            handlers
+                .commitment
+                .call(|callback| callback(&CommitmentEvent::Started { salt }));
+
+            if let Err(error) = plot.create_commitment(salt).await {
+                error!(
+                    "Failed to create commitment for {}: {}",
+                    hex::encode(salt),
+                    error
+                );
+                handlers
+                    .commitment
+                    .call(|callback| callback(&CommitmentEvent::Failed { salt }));
+            } else {
+                let duration = started.elapsed();
+                info!(
+                    "Finished recommitment for {} in {} seconds",
+                    hex::encode(salt),
+                    duration.as_secs_f32()
+                );
+                handlers
+                    .commitment
+                    .call(|callback| callback(&CommitmentEvent::Finished { salt, duration }));
+            }
+        });
+    }
+}
+
+/// `SlotHandler` handles slots one after another: keeps commitments in sync with current and next
+/// salts announced in slots and searches the plot for (locally verified) solutions.
+pub struct SlotHandler {
+    plot: Plot,
+    keypair: Keypair,
+    public_key_hash: [u8; PRIME_SIZE_BYTES],
+    spartan: Spartan<PRIME_SIZE_BYTES, PIECE_SIZE>,
+    current_salt: Option<Salt>,
+    next_salt: Option<Salt>,
+    /// Commitments that are never removed, see [`SlotHandler::preserve_commitments`]
+    preserved_salts: HashSet<Salt>,
+    metrics: Metrics,
+    control: SlotHandlerControl,
+}
+
+impl SlotHandler {
+    /// Create new slot handler for plot that was created with farmer's keypair and Spartan
+    /// instance, slot handling is reflected in provided metrics
+    pub fn new(
+        plot: Plot,
+        keypair: Keypair,
+        spartan: Spartan<PRIME_SIZE_BYTES, PIECE_SIZE>,
+        metrics: Metrics,
+    ) -> Self {
+        let public_key_hash = crypto::hash_public_key(&keypair.public);
+        let control = SlotHandlerControl {
+            plot: plot.clone(),
+            state: Arc::default(),
+            handlers: Arc::default(),
+        };
+
+        Self {
+            plot,
+            keypair,
+            public_key_hash,
+            spartan,
+            current_salt: None,
+            next_salt: None,
+            preserved_salts: HashSet::new(),
+            metrics,
+            control,
+        }
+    }
+
+    /// Never remove commitments for specified salts, neither when the first slot is handled nor on
+    /// salt changes, such that slots can be handled against farmer's plot (for instance in
+    /// simulation) without forcing farmer to recommit afterwards
+    pub fn preserve_commitments<I: IntoIterator<Item = Salt>>(&mut self, salts: I) {
+        self.preserved_salts.extend(salts);
+    }
+
+    /// Handle for inspecting and controlling slot handler from other tasks
+    pub fn control(&self) -> SlotHandlerControl {
+        self.control.clone()
+    }
+
+    /// Run callback on commitment events
+    pub fn on_commitment<F: Fn(&CommitmentEvent) + Send + Sync + 'static>(
+        &self,
+        callback: F,
+    ) -> HandlerId {
+        self.control.handlers.commitment.add(Box::new(callback))
+    }
+
+    /// Update commitments according to salts in slot info and try to find a solution for it
+    pub async fn handle_slot(
+        &mut self,
+        slot_info: &SlotInfo,
+    ) -> Result<Option<Solution>, PlotError> {
+        self.metrics.slot_received();
+        self.update_commitments(slot_info).await?;
+
+        self.find_solution(slot_info, None).await
+    }
+
+    /// Same as [`SlotHandler::handle_slot`], but search for solution is abandoned (and no
+    /// solution is returned) if it doesn't complete before `deadline`
+    pub async fn handle_slot_until(
+        &mut self,
+        slot_info: &SlotInfo,
+        deadline: Instant,
+    ) -> Result<Option<Solution>, PlotError> {
+        self.metrics.slot_received();
+        self.update_commitments(slot_info).await?;
+
+        self.find_solution(slot_info, Some(deadline)).await
+    }
+
+    async fn update_commitments(&mut self, slot_info: &SlotInfo) -> Result<(), PlotError> {
+        if self.current_salt.is_none() {
+            let mut salts = vec![slot_info.salt];
+            if let Some(salt) = slot_info.next_salt {
+                salts.push(salt);
+            }
+            salts.extend(self.preserved_salts.iter().copied());
+            self.plot.retain_commitments(salts).await?;
+        }
+
+        // Check if current salt has changed
+        if self.current_salt != Some(slot_info.salt) {
+            // If previous `next_salt` is the same as current (expected behavior) remove old
+            // commitment
+            if self.next_salt == Some(slot_info.salt) {
+                let old_salt = self.current_salt.replace(slot_info.salt);
+                if let Some(old_salt) = old_salt {
+                    info!(
+                        "Salt {} is out of date, removing commitment",
+                        hex::encode(old_salt)
+                    );
+
+                    self.remove_commitment(old_salt).await;
+                }
+            } else {
+                // `next_salt` is not the same as new salt, need to re-commit
+                info!(
+                    "Salt updated to {}, recommitting in background",
+                    hex::encode(slot_info.salt)
+                );
+                self.create_commitment(slot_info.salt);
+
+                let old_salt = self.current_salt.replace(slot_info.salt);
+                if let Some(old_salt) = old_salt {
+                    warn!(
+                        "New salt {} is not the same as previously known next salt {:?}",
+                        hex::encode(slot_info.salt),
+                        self.next_salt.map(hex::encode)
+                    );
+                    info!(
+                        "Salt {} is out of date, removing commitment",
+                        hex::encode(old_salt)
+                    );
+
+                    self.remove_commitment(old_salt).await;
+                }
+            }
+        }
+        if let Some(new_next_salt) = slot_info.next_salt {
+            if Some(new_next_salt) != self.next_salt {
+                let old_salt = self.next_salt.replace(new_next_salt);
+                if old_salt != self.current_salt {
+                    if let Some(old_salt) = old_salt {
+                        warn!(
+                            "Previous next salt {} is out of date (current is {:?}), \
+                            removing commitment",
+                            hex::encode(old_salt),
+                            self.current_salt.map(hex::encode)
+                        );
+
+                        self.remove_commitment(old_salt).await;
+                    }
+                }
+
+                info!(
+                    "Salt will update to {} soon, recommitting in background",
+                    hex::encode(new_next_salt)
+                );
+                self.create_commitment(new_next_salt);
+            }
+        }
+
+        {
+            let mut state = self.control.state.lock().unwrap();
+            state.current_salt = self.current_salt;
+            state.next_salt = self.next_salt;
+        }
+
+        Ok(())
+    }
+
+    async fn find_solution(
+        &self,
+        slot_info: &SlotInfo,
+        deadline: Option<Instant>,
+    ) -> Result<Option<Solution>, PlotError> {
+        let local_challenge =
+            crypto::derive_local_challenge(&slot_info.challenge, &self.public_key_hash);
+
+        let started = Instant::now();
+        let found = match deadline {
+            Some(deadline) => {
+                self.plot
+                    .find_by_range_until(
+                        local_challenge,
+                        slot_info.solution_range,
+                        slot_info.salt,
+                        deadline,
+                    )
+                    .await
+            }
+            None => {
+                self.plot
+                    .find_by_range(local_challenge, slot_info.solution_range, slot_info.salt)
+                    .await
+            }
+        };
+        let found = match found {
+            Ok(found) => found,
+            Err(PlotError::DeadlineExceeded) => {
+                warn!(
+                    "Search for solution for slot {} didn't complete in time, skipping",
+                    slot_info.slot_number
+                );
+                self.metrics.slot_deadline_missed();
+                return Ok(None);
+            }
+            Err(error) => {
+                return Err(error);
+            }
+        };
+        self.metrics.observe_find_by_range(started.elapsed());
+
+        let solution = match found {
+            Some((tag, index)) => {
+                let encoding = self.plot.read(index).await?;
+                let ctx = schnorrkel::context::signing_context(SIGNING_CONTEXT);
+                let solution = Solution {
+                    public_key: self.keypair.public.to_bytes(),
+                    nonce: index,
+                    encoding: encoding.to_vec(),
+                    signature: self.keypair.sign(ctx.bytes(&tag)).to_bytes().to_vec(),
+                    tag,
+                };
+
+                debug!("Solution found");
+                trace!("Solution found: {:?}", solution);
+                self.metrics.solution_found();
+
+                match verification::verify_solution(
+                    &solution,
+                    slot_info,
+                    &self.keypair.public,
+                    &self.spartan,
+                ) {
+                    Ok(()) => Some(solution),
+                    Err(error) => {
+                        error!(
+                            "Dropping invalid solution for slot {}: {}",
+                            slot_info.slot_number, error
+                        );
+                        self.metrics.solution_failed();
+                        None
+                    }
+                }
+            }
+            None => {
+                debug!("Solution not found");
+                None
+            }
+        };
+
+        Ok(solution)
+    }
+
+    fn create_commitment(&self, salt: Salt) {
+        self.control.create_commitment(salt);
+    }
+
+    async fn remove_commitment(&self, salt: Salt) {
+        if self.preserved_salts.contains(&salt) {
+            debug!(
+                "Keeping preserved commitment for {} that is out of date",
+                hex::encode(salt)
+            );
+            return;
+        }
+        if let Err(error) = self.plot.remove_commitment(salt).await {
+            error!(
+                "Failed to remove old commitment for {}: {}",
+                hex::encode(salt),
+                error
+            );
+        } else {
+            self.control
+                .handlers
+                .commitment
+                .call(|callback| callback(&CommitmentEvent::Removed { salt }));
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Minimal HTTP/1.1 server for exposing farmer internals (one request per connection)
+use async_std::future;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use log::debug;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Requests with larger head are rejected
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+/// Connections that don't send complete request head in time are closed
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) struct Request {
+    pub(crate) method: String,
+    pub(crate) path: String,
+    /// Header values by lowercase header name
+    pub(crate) headers: HashMap<String, String>,
+}
+
+impl Request {
+    /// Value of header with specified name (case-insensitive)
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+}
+
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) content_type: &'static str,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Response {
+    pub(crate) fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    pub(crate) fn not_found() -> Self {
+        Self::new(404, "text/plain", "Not found\n")
+    }
+}
+
+/// Accept connections on specified listener and respond to requests using `handler` until returned
+/// future is dropped
+pub(crate) async fn serve<H>(listener: TcpListener, handler: H) -> io::Result<()>
+where
+    H: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let handler = Arc::clone(&handler);
+
+        task::spawn(async move {
+            if let Err(error) = handle_connection(stream, handler.as_ref()).await {
+                debug!("HTTP connection with {} failed: {}", peer, error);
+            }
+        });
+    }
+}
+
+async fn handle_connection<H>(mut stream: TcpStream, handler: &H) -> io::Result<()>
+where
+    H: Fn(Request) -> Response,
+{
+    let request = future::timeout(REQUEST_TIMEOUT, read_request(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request timed out"))??;
+    let response = match request {
+        Some(request) => handler(request),
+        None => Response::new(400, "text/plain", "Bad request\n"),
+    };
+
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.flush().await
+}
+
+/// Returns `None` if request is malformed
+async fn read_request(stream: &mut TcpStream) -> io::Result<Option<Request>> {
+    let mut buffer = Vec::new();
+    let head_end = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position;
+        }
+        if buffer.len() > MAX_REQUEST_SIZE {
+            return Ok(None);
+        }
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = match std::str::from_utf8(&buffer[..head_end]) {
+        Ok(head) => head,
+        Err(_) => return Ok(None),
+    };
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (method, path) = match (request_line.next(), request_line.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(None),
+    };
+    let mut headers = HashMap::new();
+    for line in lines {
+        match line.split_once(':') {
+            Some((name, value)) => {
+                headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(Request {
+        method,
+        path,
+        headers,
+    }))
+}
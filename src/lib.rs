@@ -0,0 +1,52 @@
+//! Spartan-farmer implementation overview
+//!
+//! The application contains 2 primary commands: plot and farm.
+//!
+//! During plotting process we create a binary plot file, which contains spartan-encoded pieces one
+//! after another as well as RocksDB key-value database with tags, where key is tag (first 8 bytes
+//! of `hmac(encoding, salt)`) and value is an offset of corresponding encoded piece in the plot (we
+//! can do this because all pieces have the same size). So for every 4096 bytes we also store a
+//! record with 8-bytes tag and 8-bytes index (+some overhead of RocksDB itself).
+//!
+//! During farming process we receive a challenge and need to find a solution, given target and
+//! solution range. In order to find solution we do range query in RocksDB. For that we interpret
+//! target as 64-bit unsigned integer, and find all of the keys in tags database that are
+//! `target ± solution range` (while also handing overflow/underlow) converted back to bytes.
+//!
+//! The crate can be used as a library: [`plot::Plot`] provides plotting, commitments and search,
+//! [`crypto`] contains primitives used for encoding and tagging, [`farming`] handles slots,
+//! [`verification`] allows to check solutions and [`rpc`] has types exchanged with the node. The
+//! `spartan-farmer` binary is a thin command line wrapper around [`commands`].
+#![feature(try_blocks)]
+#![feature(hash_drain_filter)]
+#![warn(missing_docs)]
+
+pub mod api;
+pub mod commands;
+pub mod config;
+pub mod crypto;
+pub mod farming;
+mod http;
+pub mod metrics;
+pub mod plot;
+pub mod rpc;
+mod utils;
+pub mod verification;
+
+/// Encoded piece of the plot
+pub type Piece = [u8; PIECE_SIZE];
+/// Tag (commitment) of the encoded piece for particular salt
+pub type Tag = [u8; PRIME_SIZE_BYTES];
+/// Salt used for creating tags
+pub type Salt = [u8; PRIME_SIZE_BYTES];
+
+/// Size of the prime used by Spartan encoding and of tags and salts, in bytes
+pub const PRIME_SIZE_BYTES: usize = 8;
+/// Size of one piece in the plot, in bytes
+pub const PIECE_SIZE: usize = 4096;
+/// Number of rounds used for Spartan encoding
+pub const ENCODE_ROUNDS: usize = 1;
+/// Signing context used for signing tags in solutions
+pub const SIGNING_CONTEXT: &[u8] = b"FARMER";
+/// Default number of pieces processed at once (16 MiB)
+const BATCH_SIZE: u64 = (16 * 1024 * 1024 / PIECE_SIZE) as u64;
@@ -1,44 +1,31 @@
-//! Spartan-farmer implementation overview
-//!
-//! The application contains 2 primary commands: plot and farm.
-//!
-//! During plotting process we create a binary plot file, which contains spartan-encoded pieces one
-//! after another as well as RocksDB key-value database with tags, where key is tag (first 8 bytes
-//! of `hmac(encoding, salt)`) and value is an offset of corresponding encoded piece in the plot (we
-//! can do this because all pieces have the same size). So for every 4096 bytes we also store a
-//! record with 8-bytes tag and 8-bytes index (+some overhead of RocksDB itself).
-//!
-//! During farming process we receive a challenge and need to find a solution, given target and
-//! solution range. In order to find solution we do range query in RocksDB. For that we interpret
-//! target as 64-bit unsigned integer, and find all of the keys in tags database that are
-//! `target ± solution range` (while also handing overflow/underlow) converted back to bytes.
-#![feature(try_blocks)]
-#![feature(hash_drain_filter)]
-
-mod commands;
-mod crypto;
-mod plot;
-mod utils;
+//! Command line interface of spartan-farmer, see library documentation for implementation
+//! overview.
 
 use async_std::task;
 use clap::{Clap, ValueHint};
 use env_logger::Env;
-use log::info;
-use std::fs;
+use log::error;
+use spartan_farmer::commands::CommandError;
+use spartan_farmer::config::{self, Config};
+use spartan_farmer::{commands, crypto, Salt};
+use std::convert::TryInto;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-
-type Piece = [u8; PIECE_SIZE];
-type Tag = [u8; PRIME_SIZE_BYTES];
-type Salt = [u8; PRIME_SIZE_BYTES];
-
-const PRIME_SIZE_BYTES: usize = 8;
-const PIECE_SIZE: usize = 4096;
-const ENCODE_ROUNDS: usize = 1;
-const SIGNING_CONTEXT: &[u8] = b"FARMER";
-const BATCH_SIZE: u64 = (16 * 1024 * 1024 / PIECE_SIZE) as u64;
+use std::time::Duration;
+use std::{io, process};
 
 #[derive(Debug, Clap)]
 #[clap(about, version)]
+struct Cli {
+    /// Path to TOML config file (`config.toml` in data directory is used if exists by default),
+    /// command line options override values from config file
+    #[clap(long, global = true, env = "SPARTAN_CONFIG", value_hint = ValueHint::FilePath)]
+    config: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clap)]
 enum Command {
     /// Create initial plot
     Plot {
@@ -49,58 +36,397 @@ enum Command {
         plot_pieces: u64,
         /// Seed used for generating genesis piece
         seed: String,
+        /// BIP39 mnemonic to derive identity from (new identity is generated if not specified)
+        #[clap(long, env = "SPARTAN_MNEMONIC", hide_env_values = true)]
+        mnemonic: Option<String>,
+        /// Write plot bypassing page cache, recommended for large plots
+        #[clap(long, overrides_with = "no-direct-io")]
+        direct_io: bool,
+        /// Write plot through page cache even if enabled in config file
+        #[clap(long, overrides_with = "direct-io")]
+        no_direct_io: bool,
+        /// Salt (8 bytes in hex) to create commitment for while plotting, such that farmer doesn't
+        /// need to read the whole plot back to create it, can be specified multiple times
+        #[clap(long = "salt", number_of_values = 1, parse(try_from_str = parse_salt))]
+        salts: Vec<Salt>,
+        /// Fetch current and next salt from the node at this WebSocket RPC address and create
+        /// commitments for them while plotting, such that farmer is productive right away
+        #[clap(long)]
+        ws_server: Option<String>,
+        /// Number of pieces processed at once, higher values increase throughput at the cost of
+        /// memory usage (4096 bytes per piece) [default: 4096]
+        #[clap(long)]
+        batch_size: Option<u64>,
+        /// Capacity of plot's request queue of each priority [default: 100]
+        #[clap(long, parse(try_from_str = parse_queue_size))]
+        queue_size: Option<usize>,
     },
-    /// Erase existing
+    /// Erase existing plot, its commitments and identity (asks for confirmation)
     ErasePlot {
         /// Use custom path for data storage instead of platform-specific default
         #[clap(long, value_hint = ValueHint::FilePath)]
         custom_path: Option<PathBuf>,
+        /// Erase only plot and its commitments, keep identity
+        #[clap(long, conflicts_with = "commitments-only")]
+        plot_only: bool,
+        /// Erase only commitments, they will be re-created by farmer
+        #[clap(long)]
+        commitments_only: bool,
+        /// Don't ask for confirmation
+        #[clap(long)]
+        yes: bool,
     },
     /// Start a farmer using previously created plot
     Farm {
         /// Use custom path for data storage instead of platform-specific default
         #[clap(long, value_hint = ValueHint::FilePath)]
         custom_path: Option<PathBuf>,
-        #[clap(long, default_value = "ws://127.0.0.1:9944")]
-        ws_server: String,
+        /// WebSocket RPC address of the node [default: ws://127.0.0.1:9944]
+        #[clap(long)]
+        ws_server: Option<String>,
+        /// Number of attempts to reconnect after connection to the node is lost, 0 disables
+        /// reconnection [default: 10]
+        #[clap(long)]
+        reconnect_attempts: Option<u32>,
+        /// Record received slots and proposed solutions to specified file (JSON lines) for
+        /// later replay
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        record: Option<PathBuf>,
+        /// Serve Prometheus metrics over HTTP on specified address (at `/metrics`)
+        #[clap(long)]
+        metrics_address: Option<SocketAddr>,
+        /// Serve local status/control JSON API over HTTP on specified loopback address
+        #[clap(long)]
+        api_address: Option<SocketAddr>,
+        /// Read plot through memory mapping, recommended for plots on SSD
+        #[clap(long, overrides_with = "no-mmap")]
+        mmap: bool,
+        /// Don't read plot through memory mapping even if enabled in config file
+        #[clap(long, overrides_with = "mmap")]
+        no_mmap: bool,
+        /// Keep in-memory index of tags (up to 16 MiB per salt) to speed up search for solution,
+        /// recommended for very large plots
+        #[clap(long, overrides_with = "no-tag-index")]
+        tag_index: bool,
+        /// Don't keep in-memory index of tags even if enabled in config file
+        #[clap(long, overrides_with = "tag-index")]
+        no_tag_index: bool,
+        /// Number of pieces processed at once, higher values increase throughput at the cost of
+        /// memory usage (4096 bytes per piece) [default: 4096]
+        #[clap(long)]
+        batch_size: Option<u64>,
+        /// Capacity of plot's request queue of each priority [default: 100]
+        #[clap(long, parse(try_from_str = parse_queue_size))]
+        queue_size: Option<usize>,
+    },
+    /// Show information about plot: pieces, identity, commitments and inconsistencies
+    Info {
+        /// Use custom path for data storage instead of platform-specific default
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        custom_path: Option<PathBuf>,
+        /// Output information in JSON format
+        #[clap(long)]
+        json: bool,
+    },
+    /// Replay slots recorded by farmer against previously created plot and compare solutions
+    Replay {
+        /// Use custom path for data storage instead of platform-specific default
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        custom_path: Option<PathBuf>,
+        /// File with slots recorded by farm command
+        #[clap(value_hint = ValueHint::FilePath)]
+        record_file: PathBuf,
+    },
+    /// Benchmark encoding, tagging, plot I/O, commitment creation and search on this machine and
+    /// project time to plot and time to recommit
+    Bench {
+        /// Use custom path for data storage instead of platform-specific default (temporary plot
+        /// is created there in order to benchmark target disk)
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        custom_path: Option<PathBuf>,
+        /// Number of pieces in temporary plot used for benchmarking
+        #[clap(long, default_value = "4096")]
+        pieces: u64,
+        /// Number of searches to measure latency with
+        #[clap(long, default_value = "1000")]
+        searches: u64,
+        /// Number of 4096 bytes pieces in the plot to project time to plot and to recommit for
+        #[clap(long, default_value = "256000")]
+        plot_pieces: u64,
+    },
+    /// Simulate farming using previously created plot with locally generated slots instead of a
+    /// node
+    Simulate {
+        /// Use custom path for data storage instead of platform-specific default
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        custom_path: Option<PathBuf>,
+        /// Number of slots to simulate
+        #[clap(long, default_value = "100")]
+        slots: u64,
+        /// Duration of one slot in milliseconds
+        #[clap(long, default_value = "1000")]
+        slot_duration: u64,
+        /// Number of slots after which salt changes
+        #[clap(long, default_value = "20")]
+        salt_rotation: u64,
+        /// Number of slots before salt change during which next salt is announced (0 means salt
+        /// changes unexpectedly)
+        #[clap(long, default_value = "10")]
+        next_salt_announcement: u64,
+        /// Solution range (by default one tag falls into solution range on average)
+        #[clap(long)]
+        solution_range: Option<u64>,
+    },
+    /// Inspect configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Clap)]
+enum ConfigCommand {
+    /// Print effective configuration (config file with defaults and command line overrides applied)
+    Show {
+        /// Use custom path for data storage instead of platform-specific default
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        custom_path: Option<PathBuf>,
     },
 }
 
+impl Command {
+    fn custom_path(&self) -> Option<PathBuf> {
+        match self {
+            Command::Plot { custom_path, .. }
+            | Command::ErasePlot { custom_path, .. }
+            | Command::Farm { custom_path, .. }
+            | Command::Info { custom_path, .. }
+            | Command::Replay { custom_path, .. }
+            | Command::Bench { custom_path, .. }
+            | Command::Simulate { custom_path, .. }
+            | Command::Config {
+                command: ConfigCommand::Show { custom_path },
+            } => custom_path.clone(),
+        }
+    }
+}
+
 fn main() {
-    env_logger::init_from_env(Env::new().default_filter_or("info"));
+    let Cli {
+        config: config_file,
+        command,
+    } = Cli::parse();
 
-    let command: Command = Command::parse();
+    let config = load_config(config_file, command.custom_path());
 
+    let log_filter = match &config {
+        Ok(config) => config.log.filter.clone(),
+        Err(_) => config::LogConfig::default().filter,
+    };
+    env_logger::init_from_env(Env::new().default_filter_or(log_filter));
+
+    let result = config.and_then(|config| {
+        if let Some(threads) = config.threads.encoding {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build_global()
+                .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        }
+
+        run(command, config)
+    });
+
+    if let Err(error) = result {
+        error!("{}", error);
+        process::exit(error.exit_code());
+    }
+}
+
+fn run(command: Command, config: Config) -> Result<(), CommandError> {
     match command {
         Command::Plot {
             custom_path,
             plot_pieces,
             seed,
-        } => {
-            let path = utils::get_path(custom_path);
+            mnemonic,
+            direct_io,
+            no_direct_io,
+            salts,
+            ws_server,
+            batch_size,
+            queue_size,
+        } => get_path(custom_path, &config).and_then(|path| {
             task::block_on(commands::plot(
                 path,
                 crypto::genesis_piece_from_seed(&seed),
                 plot_pieces,
+                commands::PlottingOptions {
+                    mnemonic,
+                    direct_io: flag(direct_io, no_direct_io, config.plot.direct_io),
+                    salts,
+                    ws_server,
+                    batch_size: batch_size.or(config.plot.batch_size),
+                    queue_size: queue_size.or(config.plot.queue_size),
+                },
             ))
-            .unwrap();
-        }
-        Command::ErasePlot { custom_path } => {
-            let path = utils::get_path(custom_path);
-            info!("Erasing the plot");
-            fs::remove_file(path.join("plot.bin")).unwrap();
-            info!("Erasing plot metadata");
-            fs::remove_dir_all(path.join("plot-tags")).unwrap();
-            info!("Erasing identify");
-            fs::remove_file(path.join("identity.bin")).unwrap();
-            info!("Done");
+        }),
+        Command::ErasePlot {
+            custom_path,
+            plot_only,
+            commitments_only,
+            yes,
+        } => {
+            let mode = if commitments_only {
+                commands::EraseMode::Commitments
+            } else if plot_only {
+                commands::EraseMode::Plot
+            } else {
+                commands::EraseMode::All
+            };
+            get_path(custom_path, &config).and_then(|path| commands::erase_plot(&path, mode, yes))
         }
         Command::Farm {
             custom_path,
             ws_server,
-        } => {
-            let path = utils::get_path(custom_path);
-            task::block_on(commands::farm(path, &ws_server)).unwrap();
+            reconnect_attempts,
+            record,
+            metrics_address,
+            api_address,
+            mmap,
+            no_mmap,
+            tag_index,
+            no_tag_index,
+            batch_size,
+            queue_size,
+        } => get_path(custom_path, &config).and_then(|path| {
+            task::block_on(commands::farm(
+                path,
+                commands::FarmOptions {
+                    ws_server: ws_server.unwrap_or(config.node.ws_server),
+                    reconnect_attempts: reconnect_attempts
+                        .unwrap_or(config.node.reconnect_attempts),
+                    record_file: record,
+                    metrics_address: metrics_address.or(config.metrics.address),
+                    api_address: api_address.or(config.api.address),
+                    mmap: flag(mmap, no_mmap, config.plot.mmap),
+                    tag_index: flag(tag_index, no_tag_index, config.plot.tag_index),
+                    batch_size: batch_size.or(config.plot.batch_size),
+                    queue_size: queue_size.or(config.plot.queue_size),
+                },
+            ))
+        }),
+        Command::Info { custom_path, json } => {
+            get_path(custom_path, &config).and_then(|path| commands::info(&path, json))
         }
+        Command::Replay {
+            custom_path,
+            record_file,
+        } => get_path(custom_path, &config)
+            .and_then(|path| task::block_on(commands::replay(path, record_file))),
+        Command::Bench {
+            custom_path,
+            pieces,
+            searches,
+            plot_pieces,
+        } => get_path(custom_path, &config).and_then(|path| {
+            task::block_on(commands::bench(
+                path,
+                commands::BenchOptions {
+                    pieces,
+                    searches,
+                    plot_pieces,
+                },
+            ))
+        }),
+        Command::Simulate {
+            custom_path,
+            slots,
+            slot_duration,
+            salt_rotation,
+            next_salt_announcement,
+            solution_range,
+        } => get_path(custom_path, &config).and_then(|path| {
+            task::block_on(commands::simulate(
+                path,
+                commands::SimulationOptions {
+                    slots,
+                    slot_duration: Duration::from_millis(slot_duration),
+                    salt_rotation,
+                    next_salt_announcement,
+                    solution_range,
+                },
+            ))
+        }),
+        Command::Config {
+            command: ConfigCommand::Show { custom_path },
+        } => get_path(custom_path, &config).map(|path| {
+            let config = Config {
+                data_dir: Some(path),
+                ..config
+            };
+            print!("{}", config.to_toml());
+        }),
     }
 }
+
+/// Load config from explicitly specified file or from default location in data directory
+fn load_config(
+    config_file: Option<PathBuf>,
+    custom_path: Option<PathBuf>,
+) -> Result<Config, CommandError> {
+    let config = match config_file {
+        Some(config_file) => Config::load(&config_file)?,
+        None => Config::load_or_default(
+            &get_path(custom_path, &Config::default())?.join(config::CONFIG_FILE_NAME),
+        )?,
+    };
+
+    Ok(config)
+}
+
+fn get_path(custom_path: Option<PathBuf>, config: &Config) -> Result<PathBuf, CommandError> {
+    // set storage path
+    let path = match custom_path
+        .or_else(|| std::env::var("SPARTAN_DIR").map(PathBuf::from).ok())
+        .or_else(|| config.data_dir.clone())
+    {
+        Some(path) => path,
+        None => dirs::data_local_dir()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "Can't find local data directory, needs to be specified explicitly",
+                )
+            })?
+            .join("spartan"),
+    };
+
+    Ok(path)
+}
+
+/// Value of boolean option that can be enabled with `--<option>` or disabled with
+/// `--no-<option>` on command line, overriding config file
+fn flag(enabled: bool, disabled: bool, config: bool) -> bool {
+    if enabled {
+        true
+    } else if disabled {
+        false
+    } else {
+        config
+    }
+}
+
+fn parse_queue_size(queue_size: &str) -> Result<usize, String> {
+    match queue_size.parse() {
+        Ok(0) => Err("Queue size must be at least 1".to_string()),
+        Ok(queue_size) => Ok(queue_size),
+        Err(error) => Err(format!("Invalid queue size: {}", error)),
+    }
+}
+
+fn parse_salt(salt: &str) -> Result<Salt, String> {
+    hex::decode(salt)
+        .ok()
+        .and_then(|salt| salt.as_slice().try_into().ok())
+        .ok_or_else(|| "Salt must be 8 bytes in hex".to_string())
+}
@@ -0,0 +1,316 @@
+//! Prometheus metrics of the farmer
+use crate::http::{self, Request, Response};
+use crate::plot::Plot;
+use crate::PIECE_SIZE;
+use async_std::net::TcpListener;
+use prometheus::core::{Atomic, GenericCounter};
+use prometheus::{
+    CounterVec, Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec,
+    IntGauge, Opts, Registry, TextEncoder,
+};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Metrics collected while farming, cheap to clone (clones share the same metrics)
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    /// Counters mirrored from the plot are reset and set again on every encoding, which must not
+    /// happen concurrently
+    encoding: Arc<Mutex<()>>,
+    slots_received: IntCounter,
+    solutions_found: IntCounter,
+    solutions_submitted: IntCounter,
+    solutions_failed: IntCounter,
+    slot_deadlines_missed: IntCounter,
+    slots_skipped: IntCounter,
+    find_by_range_latency: Histogram,
+    slot_latency: Histogram,
+    rpc_connections: IntCounter,
+    plot_pieces: IntGauge,
+    plot_size: IntGauge,
+    read_queue_depth: IntGauge,
+    write_queue_depth: IntGauge,
+    canceled_requests: IntCounter,
+    queue_wait: CounterVec,
+    queue_wait_max: GaugeVec,
+    queue_dispatched: IntCounterVec,
+    commitment_progress: GaugeVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Create new set of metrics registered in its own registry
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("spartan_farmer".to_string()), None).unwrap();
+
+        let counter = |name: &str, help: &str| {
+            let counter = IntCounter::new(name, help).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let gauge = |name: &str, help: &str| {
+            let gauge = IntGauge::new(name, help).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+
+        let slots_received = counter("slots_received_total", "Number of slots received");
+        let solutions_found = counter(
+            "solutions_found_total",
+            "Number of solutions found in the plot",
+        );
+        let solutions_submitted = counter(
+            "solutions_submitted_total",
+            "Number of solutions submitted to the node",
+        );
+        let solutions_failed = counter(
+            "solutions_failed_total",
+            "Number of solutions dropped because they failed local verification",
+        );
+        let slot_deadlines_missed = counter(
+            "slot_deadlines_missed_total",
+            "Number of slots for which search for solution didn't complete in time",
+        );
+        let slots_skipped = counter(
+            "slots_skipped_total",
+            "Number of slots skipped because newer slot arrived before farmer got to them",
+        );
+        let rpc_connections = counter(
+            "rpc_connections_total",
+            "Number of connections established to the node",
+        );
+        let plot_pieces = gauge("plot_pieces", "Number of pieces in the plot");
+        let plot_size = gauge("plot_size_bytes", "Size of the plot in bytes");
+        let read_queue_depth = gauge(
+            "plot_read_queue_depth",
+            "Number of read requests waiting to be handled by the plot",
+        );
+        let write_queue_depth = gauge(
+            "plot_write_queue_depth",
+            "Number of write requests waiting to be handled by the plot",
+        );
+        let canceled_requests = counter(
+            "plot_requests_canceled_total",
+            "Number of plot read requests skipped because caller stopped waiting for them",
+        );
+
+        let find_by_range_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "find_by_range_latency_seconds",
+                "Time it takes to search the plot for solution",
+            )
+            .buckets(vec![
+                0.000_1, 0.000_25, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25,
+                0.5, 1.0,
+            ]),
+        )
+        .unwrap();
+        registry
+            .register(Box::new(find_by_range_latency.clone()))
+            .unwrap();
+
+        let slot_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "slot_latency_seconds",
+                "Time from receiving slot notification to proposal being accepted by the node",
+            )
+            .buckets(vec![
+                0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+            ]),
+        )
+        .unwrap();
+        registry.register(Box::new(slot_latency.clone())).unwrap();
+
+        let gauge_vec = |name: &str, help: &str, label: &str| {
+            let gauge = GaugeVec::new(Opts::new(name, help), &[label]).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+        let queue_wait = CounterVec::new(
+            Opts::new(
+                "plot_queue_wait_seconds_total",
+                "Total time dispatched requests spent waiting in plot's queue by priority",
+            ),
+            &["priority"],
+        )
+        .unwrap();
+        registry.register(Box::new(queue_wait.clone())).unwrap();
+        let queue_wait_max = gauge_vec(
+            "plot_queue_wait_max_seconds",
+            "Longest time a request spent waiting in plot's queue by priority",
+            "priority",
+        );
+        let queue_dispatched = IntCounterVec::new(
+            Opts::new(
+                "plot_queue_dispatched_total",
+                "Number of requests dispatched from plot's queue by priority",
+            ),
+            &["priority"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(queue_dispatched.clone()))
+            .unwrap();
+
+        let commitment_progress = GaugeVec::new(
+            Opts::new(
+                "commitment_progress_ratio",
+                "Fraction of the plot committed to for each salt",
+            ),
+            &["salt"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(commitment_progress.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            encoding: Arc::default(),
+            slots_received,
+            solutions_found,
+            solutions_submitted,
+            solutions_failed,
+            slot_deadlines_missed,
+            slots_skipped,
+            find_by_range_latency,
+            slot_latency,
+            rpc_connections,
+            plot_pieces,
+            plot_size,
+            read_queue_depth,
+            write_queue_depth,
+            canceled_requests,
+            queue_wait,
+            queue_wait_max,
+            queue_dispatched,
+            commitment_progress,
+        }
+    }
+
+    pub(crate) fn slot_received(&self) {
+        self.slots_received.inc();
+    }
+
+    pub(crate) fn solution_found(&self) {
+        self.solutions_found.inc();
+    }
+
+    pub(crate) fn solution_submitted(&self) {
+        self.solutions_submitted.inc();
+    }
+
+    pub(crate) fn solution_failed(&self) {
+        self.solutions_failed.inc();
+    }
+
+    pub(crate) fn slot_deadline_missed(&self) {
+        self.slot_deadlines_missed.inc();
+    }
+
+    pub(crate) fn slot_skipped(&self) {
+        self.slots_skipped.inc();
+    }
+
+    pub(crate) fn rpc_connected(&self) {
+        self.rpc_connections.inc();
+    }
+
+    pub(crate) fn observe_find_by_range(&self, latency: Duration) {
+        self.find_by_range_latency.observe(latency.as_secs_f64());
+    }
+
+    pub(crate) fn observe_slot_latency(&self, latency: Duration) {
+        self.slot_latency.observe(latency.as_secs_f64());
+    }
+
+    /// Encode all metrics in Prometheus text format, plot-related metrics are read from `plot`
+    pub fn encode(&self, plot: &Plot) -> String {
+        let _guard = self.encoding.lock().unwrap();
+        let piece_count = plot.piece_count();
+        self.plot_pieces.set(piece_count as i64);
+        self.plot_size.set((piece_count * PIECE_SIZE as u64) as i64);
+        self.read_queue_depth.set(plot.read_queue_depth() as i64);
+        self.write_queue_depth.set(plot.write_queue_depth() as i64);
+        set_counter(&self.canceled_requests, plot.canceled_requests());
+        for (priority, stats) in plot.queue_wait_stats() {
+            let priority = priority.to_string();
+            set_counter(
+                &self.queue_wait.with_label_values(&[&priority]),
+                stats.total.as_secs_f64(),
+            );
+            self.queue_wait_max
+                .with_label_values(&[&priority])
+                .set(stats.max.as_secs_f64());
+            set_counter(
+                &self.queue_dispatched.with_label_values(&[&priority]),
+                stats.requests,
+            );
+        }
+
+        self.commitment_progress.reset();
+        for (salt, progress) in plot.commitment_progress() {
+            let gauge: Gauge = self
+                .commitment_progress
+                .with_label_values(&[&hex::encode(salt)]);
+            gauge.set(if piece_count == 0 {
+                0_f64
+            } else {
+                progress as f64 / piece_count as f64
+            });
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+/// Set counter to the value of a counter tracked elsewhere
+fn set_counter<P: Atomic>(counter: &GenericCounter<P>, value: P::T) {
+    counter.reset();
+    counter.inc_by(value);
+}
+
+/// Serve metrics at `/metrics` over HTTP on specified listener until returned future is dropped
+pub async fn serve(listener: TcpListener, metrics: Metrics, plot: Plot) -> io::Result<()> {
+    http::serve(listener, move |request: Request| {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => {
+                Response::new(200, "text/plain; version=0.0.4", metrics.encode(&plot))
+            }
+            (_, "/metrics") => Response::new(405, "text/plain", "Method not allowed\n"),
+            _ => Response::not_found(),
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metric_names() {
+        let metrics = Metrics::new();
+        metrics.slot_received();
+        metrics.observe_find_by_range(Duration::from_millis(1));
+
+        let families = metrics.registry.gather();
+        let names = families
+            .iter()
+            .map(|family| family.get_name())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"spartan_farmer_slots_received_total"));
+        assert!(names.contains(&"spartan_farmer_find_by_range_latency_seconds"));
+    }
+}
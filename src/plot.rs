@@ -1,27 +1,45 @@
+//! Plot and commitments storage
 mod commitments;
+mod direct_io;
+mod lock;
+mod mapping;
+mod scheduler;
+mod tag_index;
 
+pub use crate::plot::commitments::CommitmentError;
 use crate::plot::commitments::Commitments;
+use crate::plot::direct_io::AlignedBuffer;
+pub(crate) use crate::plot::lock::{DirectoryLock, LOCK_FILE_NAME};
+use crate::plot::mapping::Mapping;
+use crate::plot::scheduler::SchedulerSender;
+pub use crate::plot::scheduler::{QueueWaitStats, RequestPriority};
+use crate::plot::tag_index::TagIndexes;
 use crate::{crypto, utils, Piece, Salt, Tag, BATCH_SIZE, PIECE_SIZE};
-use async_std::fs::OpenOptions;
+use async_std::future;
 use async_std::path::PathBuf;
 use async_std::task;
 use event_listener_primitives::{BagOnce, HandlerId};
-use futures::channel::mpsc as async_mpsc;
 use futures::channel::oneshot;
-use futures::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SinkExt, StreamExt};
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
 use log::{error, trace};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::fs::OpenOptions;
 use std::io;
-use std::io::SeekFrom;
+use std::os::unix::fs::FileExt;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use thiserror::Error;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-enum CommitmentStatus {
+/// Status of commitment to the plot for particular salt
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommitmentStatus {
     /// In-progress commitment to the part of the plot
     InProgress,
     /// Commitment to the whole plot and not some in-progress partial commitment
@@ -30,57 +48,205 @@ enum CommitmentStatus {
     Aborted,
 }
 
+/// Default capacity of plot's request queue of each priority
+const QUEUE_SIZE: usize = 100;
+
+/// Options for opening the plot
+#[derive(Debug, Clone)]
+pub struct PlotOptions {
+    /// Open existing plot for reading only, see [`Plot::open_read_only`]
+    pub read_only: bool,
+    /// Serve [`Plot::read`] and [`Plot::read_pieces`] from memory-mapped plot file directly
+    /// instead of going through plot's I/O queue, recommended for plots on SSD. Ignored for
+    /// read-only plots, since they are not locked and can be truncated by another process.
+    ///
+    /// NOTE: I/O errors while reading from mapping (failing disk or plot file truncated by another
+    /// process despite the lock) are delivered as SIGBUS, handler for which is installed that
+    /// recovers from it, after which the plot file is read directly and errors are returned as
+    /// [`PlotError::Io`].
+    pub mmap: bool,
+    /// Write encodings bypassing page cache (`O_DIRECT` on Linux, `F_NOCACHE` on macOS), such
+    /// that plotting doesn't evict everything else from memory, falls back to buffered writes if
+    /// file system doesn't support it
+    pub direct_io: bool,
+    /// Sync plot file (`fdatasync`) after every specified number of bytes written instead of only
+    /// when plot is closed, which avoids long writeback stalls
+    pub sync_interval: Option<u64>,
+    /// Reserve disk space for specified number of pieces upfront, plot size is not changed
+    pub preallocate: Option<u64>,
+    /// Number of pieces read and tagged at once when creating commitment, higher values increase
+    /// throughput at the cost of memory usage (`batch_size * PIECE_SIZE` bytes per batch)
+    pub batch_size: u64,
+    /// Capacity of request queue of each [`RequestPriority`] (at least 1), requests wait for space
+    /// in the queue when it is full
+    pub queue_size: usize,
+    /// Keep sparse in-memory index of tags of every created commitment (up to 16 MiB each), such
+    /// that [`Plot::find_by_range`] doesn't touch the database when there are no tags in range
+    /// and seeks directly to the right place otherwise, index is built in background when
+    /// commitment is created or plot is opened
+    pub tag_index: bool,
+}
+
+impl Default for PlotOptions {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            mmap: false,
+            direct_io: false,
+            sync_interval: None,
+            preallocate: None,
+            batch_size: BATCH_SIZE,
+            queue_size: QUEUE_SIZE,
+            tag_index: false,
+        }
+    }
+}
+
+/// Errors that happen during plot operations
 #[derive(Debug, Error)]
-pub(crate) enum PlotError {
+pub enum PlotError {
+    /// Failed to open plot file
     #[error("Plot open error: {0}")]
     PlotOpen(io::Error),
+    /// Failed to open commitments
     #[error("Plot commitments open error: {0}")]
     PlotCommitmentsOpen(io::Error),
+    /// Failed to read from or write to the plot file
+    #[error("Plot I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// Failed to read or update commitments
+    #[error("Plot commitments error: {0}")]
+    Commitments(#[from] CommitmentError),
+    /// Plot was closed and can't handle requests anymore
+    #[error("Plot is closed")]
+    Closed,
+    /// Plot is opened in read-only mode and can't be modified
+    #[error("Plot is opened in read-only mode")]
+    ReadOnly,
+    /// Plot is used by another process
+    #[error(
+        "Plot is used by another process{}, stop it first",
+        .pid.map(|pid| format!(" (PID {})", pid)).unwrap_or_default()
+    )]
+    Locked {
+        /// PID of the process holding the lock if known
+        pid: Option<u32>,
+    },
+    /// Request didn't complete before deadline and was canceled
+    #[error("Plot request deadline exceeded")]
+    DeadlineExceeded,
+    /// Commitment creation was aborted because commitment was removed in the meantime
+    #[error("Commitment creation was aborted")]
+    CommitmentAborted,
+}
+
+/// Decode tag and index of the piece it belongs to from commitment database entry
+fn decode_tag_entry(tag: &[u8], index: Option<&[u8]>) -> Result<(Tag, u64), CommitmentError> {
+    let tag = tag.try_into().map_err(|_| CommitmentError::CorruptEntry)?;
+    let index = index
+        .and_then(|index| index.try_into().ok())
+        .ok_or(CommitmentError::CorruptEntry)?;
+
+    Ok((tag, u64::from_le_bytes(index)))
+}
+
+/// Reads statuses of commitments of the plot in specified directory without opening the plot,
+/// returns `None` if plot doesn't have commitments metadata yet
+pub fn read_commitment_statuses(
+    path: &std::path::Path,
+) -> Result<Option<HashMap<Salt, CommitmentStatus>>, PlotError> {
+    let statuses = commitments::read_metadata(&path.join("plot-tags"))?;
+
+    Ok(statuses.map(|statuses| {
+        statuses
+            .into_iter()
+            .map(|(salt, status)| {
+                let status = match status {
+                    commitments::CommitmentStatus::InProgress => CommitmentStatus::InProgress,
+                    commitments::CommitmentStatus::Created => CommitmentStatus::Created,
+                };
+                (salt, status)
+            })
+            .collect()
+    }))
 }
 
 #[derive(Debug)]
 enum ReadRequests {
     ReadEncoding {
         index: u64,
-        result_sender: oneshot::Sender<io::Result<Piece>>,
+        result_sender: oneshot::Sender<Result<Piece, PlotError>>,
     },
     ReadEncodings {
         first_index: u64,
         count: u64,
         /// Vector containing all of the pieces as contiguous block of memory
-        result_sender: oneshot::Sender<io::Result<Vec<u8>>>,
+        result_sender: oneshot::Sender<Result<Vec<u8>, PlotError>>,
     },
     FindByRange {
         target: Tag,
         range: u64,
         salt: Salt,
-        result_sender: oneshot::Sender<io::Result<Option<(Tag, u64)>>>,
+        result_sender: oneshot::Sender<Result<Option<(Tag, u64)>, PlotError>>,
     },
 }
 
+impl ReadRequests {
+    /// Whether caller is no longer waiting for result
+    fn is_canceled(&self) -> bool {
+        match self {
+            ReadRequests::ReadEncoding { result_sender, .. } => result_sender.is_canceled(),
+            ReadRequests::ReadEncodings { result_sender, .. } => result_sender.is_canceled(),
+            ReadRequests::FindByRange { result_sender, .. } => result_sender.is_canceled(),
+        }
+    }
+}
+
 #[derive(Debug)]
 enum WriteRequests {
     WriteEncodings {
         encodings: Vec<Piece>,
         first_index: u64,
-        result_sender: oneshot::Sender<io::Result<()>>,
+        result_sender: oneshot::Sender<Result<(), PlotError>>,
     },
     WriteTags {
         first_index: u64,
         tags: Vec<Tag>,
         salt: Salt,
-        result_sender: oneshot::Sender<io::Result<()>>,
+        result_sender: oneshot::Sender<Result<(), PlotError>>,
     },
     FinishCommitmentCreation {
         salt: Salt,
-        result_sender: oneshot::Sender<()>,
+        result_sender: oneshot::Sender<Result<(), PlotError>>,
     },
     RemoveCommitment {
         salt: Salt,
-        result_sender: oneshot::Sender<()>,
+        result_sender: oneshot::Sender<Result<(), PlotError>>,
     },
 }
 
+#[derive(Debug)]
+enum Request {
+    Read(ReadRequests),
+    Write(WriteRequests),
+}
+
+impl Request {
+    fn priority(&self) -> RequestPriority {
+        match self {
+            Request::Read(ReadRequests::ReadEncoding { .. })
+            | Request::Read(ReadRequests::FindByRange { .. }) => RequestPriority::SolutionLookup,
+            Request::Read(ReadRequests::ReadEncodings { .. }) => RequestPriority::CommitmentRead,
+            Request::Write(WriteRequests::WriteEncodings { .. })
+            | Request::Write(WriteRequests::WriteTags { .. }) => RequestPriority::Write,
+            Request::Write(WriteRequests::FinishCommitmentCreation { .. })
+            | Request::Write(WriteRequests::RemoveCommitment { .. }) => {
+                RequestPriority::Maintenance
+            }
+        }
+    }
+}
+
 #[derive(Default)]
 struct Handlers {
     close: BagOnce<Box<dyn FnOnce() + Send>>,
@@ -88,134 +254,228 @@ struct Handlers {
 
 struct Inner {
     handlers: Arc<Handlers>,
-    any_requests_sender: async_mpsc::Sender<()>,
-    read_requests_sender: async_mpsc::Sender<ReadRequests>,
-    write_requests_sender: async_mpsc::Sender<WriteRequests>,
+    read_only: bool,
+    mapping: Option<Mapping>,
+    batch_size: u64,
+    requests_sender: SchedulerSender<Request>,
     piece_count: Arc<AtomicU64>,
+    read_queue_depth: Arc<AtomicU64>,
+    write_queue_depth: Arc<AtomicU64>,
+    canceled_requests: Arc<AtomicU64>,
     commitment_statuses: Mutex<HashMap<Salt, CommitmentStatus>>,
+    /// Number of pieces committed so far for commitments that are in progress
+    commitment_progress: Mutex<HashMap<Salt, u64>>,
+    tag_indexes: TagIndexes,
 }
 
 /// `Plot` struct is an abstraction on top of both plot and tags database. It converts async
-/// requests to internal reads/writes to the plot and tags database. It prioritizes reads over
-/// writes. Internally for that it has 2 queues for reads and writes requests, read requests are
-/// executed until exhausted after which at most 1 write request is handled and cycle repeats. This
-/// allows finding solution with as little delay as possible while introducing changes to the plot
-/// at the same time (re-plotting on salt changes or extending plot size).
+/// requests to internal reads/writes to the plot and tags database. Internally it has a queue for
+/// each [`RequestPriority`] and dispatches requests strictly by priority, except that request that
+/// was bypassed by too many requests of higher priority is dispatched next. This allows finding
+/// solution with as little delay as possible while introducing changes to the plot at the same
+/// time (re-plotting on salt changes or extending plot size) without writes being delayed
+/// indefinitely by heavy reads. Time requests spend in queues is available through
+/// [`Plot::queue_wait_stats`].
+///
+/// Encodings are read with positional reads by multiple workers concurrently, so reading single
+/// encoding is not blocked by bulk reads, while write requests wait for reads that were issued
+/// before them to finish.
 #[derive(Clone)]
-pub(crate) struct Plot {
+pub struct Plot {
     inner: Arc<Inner>,
 }
 
 impl Plot {
-    /// Creates a new plot for persisting encoded pieces to disk
-    pub(crate) async fn open_or_create(path: &PathBuf) -> Result<Plot, PlotError> {
-        let mut plot_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path.join("plot.bin"))
-            .await
-            .map_err(PlotError::PlotOpen)?;
+    /// Creates a new plot for persisting encoded pieces to disk.
+    ///
+    /// Data directory is locked exclusively while plot is open, opening it again (from this or
+    /// another process) fails with [`PlotError::Locked`] until plot is closed, use
+    /// [`Plot::open_read_only`] for inspection instead.
+    pub async fn open_or_create(path: &PathBuf) -> Result<Plot, PlotError> {
+        Self::open_with_options(path, PlotOptions::default()).await
+    }
 
-        let plot_size = plot_file
-            .metadata()
-            .await
-            .map_err(PlotError::PlotOpen)?
-            .len();
+    /// Opens existing plot for reading without locking data directory, such that it can be
+    /// inspected while another process is using it. No files are created or modified: plot file
+    /// and commitments are opened read-only, commitments left unfinished by previous runs are
+    /// ignored and all write operations fail with [`PlotError::ReadOnly`].
+    pub async fn open_read_only(path: &PathBuf) -> Result<Plot, PlotError> {
+        Self::open_with_options(
+            path,
+            PlotOptions {
+                read_only: true,
+                ..PlotOptions::default()
+            },
+        )
+        .await
+    }
+
+    /// Opens the plot with custom options, see [`Plot::open_or_create`] and
+    /// [`Plot::open_read_only`] for details
+    pub async fn open_with_options(
+        path: &PathBuf,
+        options: PlotOptions,
+    ) -> Result<Plot, PlotError> {
+        let PlotOptions {
+            read_only,
+            mmap,
+            direct_io,
+            sync_interval,
+            preallocate,
+            batch_size,
+            queue_size,
+            tag_index,
+        } = options;
+        // Batch must have at least one piece in it to make progress, same for queue
+        let batch_size = batch_size.max(1);
+        let queue_size = queue_size.max(1);
+        let lock = if read_only {
+            None
+        } else {
+            Some(DirectoryLock::acquire(path.as_ref())?)
+        };
+
+        // Shared with blocking workers, all reads and writes are positional
+        let plot_file = Arc::new(
+            OpenOptions::new()
+                .read(true)
+                .write(!read_only)
+                .create(!read_only)
+                .open(path.join("plot.bin"))
+                .map_err(PlotError::PlotOpen)?,
+        );
+
+        let plot_size = plot_file.metadata().map_err(PlotError::PlotOpen)?.len();
+        let mut direct_file = None;
+        if !read_only {
+            if let Some(piece_count) = preallocate {
+                direct_io::preallocate(&plot_file, piece_count * PIECE_SIZE as u64)
+                    .map_err(PlotError::PlotOpen)?;
+            }
+            if direct_io {
+                direct_file = direct_io::open_direct(path.join("plot.bin").as_ref())
+                    .map_err(PlotError::PlotOpen)?
+                    .map(Arc::new);
+            }
+        }
+        // Plot that is not locked could be truncated underneath the mapping
+        let mapping = if mmap && !read_only {
+            Some(Mapping::new(Arc::clone(&plot_file)))
+        } else {
+            None
+        };
 
         let piece_count = Arc::new(AtomicU64::new(plot_size / PIECE_SIZE as u64));
 
-        // Channel with at most single element to throttle loop below if there are no updates
-        let (any_requests_sender, mut any_requests_receiver) = async_mpsc::channel::<()>(1);
-        let (read_requests_sender, mut read_requests_receiver) =
-            async_mpsc::channel::<ReadRequests>(100);
-        let (write_requests_sender, mut write_requests_receiver) =
-            async_mpsc::channel::<WriteRequests>(100);
+        let (requests_sender, mut scheduler) = scheduler::channel::<Request>(queue_size);
+
+        let read_queue_depth = Arc::new(AtomicU64::new(0));
+        let write_queue_depth = Arc::new(AtomicU64::new(0));
+        let canceled_requests = Arc::new(AtomicU64::new(0));
 
         let handlers = Arc::new(Handlers::default());
-        let tags_dbs_fut = Commitments::new(path.join("plot-tags"));
+        let tags_dbs_fut = Commitments::new(path.join("plot-tags"), read_only);
         let mut tags_dbs = tags_dbs_fut.await.map_err(PlotError::PlotCommitmentsOpen)?;
         let commitment_statuses: HashMap<Salt, CommitmentStatus> = tags_dbs
             .get_existing_commitments()
             .map(|&salt| (salt, CommitmentStatus::Created))
             .collect();
+        let tag_indexes = TagIndexes::default();
 
         task::spawn({
             let handlers = Arc::clone(&handlers);
             let piece_count = Arc::clone(&piece_count);
+            let read_queue_depth = Arc::clone(&read_queue_depth);
+            let write_queue_depth = Arc::clone(&write_queue_depth);
+            let canceled_requests = Arc::clone(&canceled_requests);
+            let tag_indexes = tag_indexes.clone();
+            let existing_salts = commitment_statuses.keys().copied().collect::<Vec<_>>();
 
             async move {
-                let mut did_nothing = true;
-                'outer: loop {
-                    if did_nothing {
-                        // Wait for stuff to come in
-                        if any_requests_receiver.next().await.is_none() {
-                            break;
-                        }
-                    }
-
-                    did_nothing = true;
-
-                    // Process as many read requests as there is
-                    while let Ok(read_request) = read_requests_receiver.try_next() {
-                        did_nothing = false;
-
-                        match read_request {
-                            Some(ReadRequests::ReadEncoding {
-                                index,
-                                result_sender,
-                            }) => {
-                                let _ = result_sender.send(
-                                    try {
-                                        plot_file
-                                            .seek(SeekFrom::Start(index * PIECE_SIZE as u64))
-                                            .await?;
-                                        let mut buffer = [0u8; PIECE_SIZE];
-                                        plot_file.read_exact(&mut buffer).await?;
-                                        buffer
-                                    },
-                                );
+                if tag_index {
+                    let piece_count = piece_count.load(Ordering::Acquire);
+                    for salt in existing_salts {
+                        match tags_dbs.get_db(salt).await {
+                            Ok(Some(tags_db)) => {
+                                tag_indexes.build(salt, tags_db, piece_count);
                             }
-                            Some(ReadRequests::ReadEncodings {
-                                first_index,
-                                count,
-                                result_sender,
-                            }) => {
-                                let _ = result_sender.send(
-                                    try {
-                                        plot_file
-                                            .seek(SeekFrom::Start(first_index * PIECE_SIZE as u64))
-                                            .await?;
-                                        let mut buffer =
-                                            Vec::with_capacity(count as usize * PIECE_SIZE);
-                                        buffer.resize(buffer.capacity(), 0);
-                                        plot_file.read_exact(&mut buffer).await?;
-                                        buffer
-                                    },
+                            Ok(None) => {}
+                            Err(error) => {
+                                error!(
+                                    "Failed to open commitment {} for indexing: {}",
+                                    hex::encode(salt),
+                                    error
                                 );
                             }
-                            None => {
-                                break 'outer;
-                            }
-                            Some(ReadRequests::FindByRange {
-                                target,
-                                range,
-                                salt,
-                                result_sender,
-                            }) => {
-                                let tags_db = match tags_dbs.get_or_create_db(salt).await {
-                                    Ok(tags_db) => tags_db,
-                                    Err(error) => {
-                                        error!("Failed to open tags database: {}", error);
-                                        continue;
-                                    }
-                                };
-                                // TODO: Remove unwrap
-                                let solutions = utils::spawn_blocking(move || {
-                                    let mut iter = tags_db.raw_iterator();
+                        }
+                    }
+                }
 
-                                    let mut solutions: Vec<(Tag, u64)> = Vec::new();
+                // Reads of encodings are served concurrently by blocking workers, such that reading
+                // single encoding for solution is never blocked by bulk reads for commitments
+                let mut in_flight_reads = FuturesUnordered::new();
+                // Bytes written since plot file was synced last time
+                let mut unsynced_bytes = 0u64;
+                while let Some(request) = scheduler.next().await {
+                    // Clean up finished reads
+                    while let Some(Some(())) = in_flight_reads.next().now_or_never() {}
+
+                    match request {
+                        Request::Read(read_request) => {
+                            read_queue_depth.fetch_sub(1, Ordering::AcqRel);
+                            // Caller has gone away (for instance its deadline has passed), no need
+                            // to do anything
+                            if read_request.is_canceled() {
+                                trace!("Skipping canceled read request");
+                                canceled_requests.fetch_add(1, Ordering::AcqRel);
+                                continue;
+                            }
 
+                            match read_request {
+                                ReadRequests::ReadEncoding {
+                                    index,
+                                    result_sender,
+                                } => {
+                                    let plot_file = Arc::clone(&plot_file);
+                                    in_flight_reads.push(utils::spawn_blocking(move || {
+                                        let _ = result_sender.send(
+                                            try {
+                                                let mut buffer = [0u8; PIECE_SIZE];
+                                                plot_file.read_exact_at(
+                                                    &mut buffer,
+                                                    index * PIECE_SIZE as u64,
+                                                )?;
+                                                buffer
+                                            },
+                                        );
+                                    }));
+                                }
+                                ReadRequests::ReadEncodings {
+                                    first_index,
+                                    count,
+                                    result_sender,
+                                } => {
+                                    let plot_file = Arc::clone(&plot_file);
+                                    in_flight_reads.push(utils::spawn_blocking(move || {
+                                        let _ = result_sender.send(
+                                            try {
+                                                let mut buffer =
+                                                    vec![0u8; count as usize * PIECE_SIZE];
+                                                plot_file.read_exact_at(
+                                                    &mut buffer,
+                                                    first_index * PIECE_SIZE as u64,
+                                                )?;
+                                                buffer
+                                            },
+                                        );
+                                    }));
+                                }
+                                ReadRequests::FindByRange {
+                                    target,
+                                    range,
+                                    salt,
+                                    result_sender,
+                                } => {
                                     let (lower, is_lower_overflowed) =
                                         u64::from_be_bytes(target).overflowing_sub(range / 2);
                                     let (upper, is_upper_overflowed) =
@@ -228,151 +488,231 @@ impl Plot {
                                         is_upper_overflowed
                                     );
 
-                                    if is_lower_overflowed || is_upper_overflowed {
-                                        iter.seek_to_first();
-                                        while let Some(tag) = iter.key() {
-                                            let tag = tag.try_into().unwrap();
-                                            let index = iter.value().unwrap();
-                                            if u64::from_be_bytes(tag) <= upper {
-                                                solutions.push((
-                                                    tag,
-                                                    u64::from_le_bytes(index.try_into().unwrap()),
-                                                ));
-                                                iter.next();
+                                    // Where to seek for tags under wrapped upper edge and where to
+                                    // seek for tags in range otherwise, `None` if there are no
+                                    // tags there
+                                    let (wrapped_start, start) = match tag_indexes.get(&salt) {
+                                        Some(tag_index) => {
+                                            if is_lower_overflowed || is_upper_overflowed {
+                                                (
+                                                    tag_index.seek_hint(0, upper),
+                                                    tag_index.seek_hint(lower, u64::MAX),
+                                                )
                                             } else {
-                                                break;
+                                                (None, tag_index.seek_hint(lower, upper))
                                             }
                                         }
-                                        iter.seek(lower.to_be_bytes());
-                                        while let Some(tag) = iter.key() {
-                                            let tag = tag.try_into().unwrap();
-                                            let index = iter.value().unwrap();
-
-                                            solutions.push((
-                                                tag,
-                                                u64::from_le_bytes(index.try_into().unwrap()),
-                                            ));
-                                            iter.next();
-                                        }
-                                    } else {
-                                        iter.seek(lower.to_be_bytes());
-                                        while let Some(tag) = iter.key() {
-                                            let tag = tag.try_into().unwrap();
-                                            let index = iter.value().unwrap();
-                                            if u64::from_be_bytes(tag) <= upper {
-                                                solutions.push((
-                                                    tag,
-                                                    u64::from_le_bytes(index.try_into().unwrap()),
-                                                ));
-                                                iter.next();
+                                        None => {
+                                            if is_lower_overflowed || is_upper_overflowed {
+                                                (Some(0), Some(lower))
                                             } else {
-                                                break;
+                                                (None, Some(lower))
                                             }
                                         }
+                                    };
+                                    if wrapped_start.is_none() && start.is_none() {
+                                        let _ = result_sender.send(Ok(None));
+                                        continue;
                                     }
 
-                                    solutions
-                                })
-                                .await;
-
-                                let _ = result_sender.send(Ok(solutions.into_iter().next()));
-                            }
-                        }
-                    }
-
-                    let write_request = write_requests_receiver.try_next();
-                    if write_request.is_ok() {
-                        did_nothing = false;
-                    }
-                    // Process at most write request since reading is higher priority
-                    match write_request {
-                        Ok(Some(WriteRequests::WriteEncodings {
-                            encodings,
-                            first_index,
-                            result_sender,
-                        })) => {
-                            let _ = result_sender.send(
-                                try {
-                                    plot_file
-                                        .seek(SeekFrom::Start(first_index * PIECE_SIZE as u64))
-                                        .await?;
-                                    {
-                                        let mut whole_encoding = Vec::with_capacity(
-                                            encodings[0].len() * encodings.len(),
-                                        );
-                                        for encoding in &encodings {
-                                            whole_encoding.extend_from_slice(encoding);
+                                    let tags_db = match tags_dbs.get_db(salt).await {
+                                        Ok(Some(tags_db)) => tags_db,
+                                        Ok(None) => {
+                                            let _ = result_sender.send(Ok(None));
+                                            continue;
                                         }
-                                        plot_file.write_all(&whole_encoding).await?;
-                                        piece_count.fetch_max(
-                                            first_index + encodings.len() as u64,
-                                            Ordering::AcqRel,
-                                        );
-                                    }
-                                },
-                            );
-                        }
-                        Ok(Some(WriteRequests::WriteTags {
-                            first_index,
-                            tags,
-                            salt,
-                            result_sender,
-                        })) => {
-                            let _ = result_sender.send(
-                                try {
-                                    let tags_db = match tags_dbs.get_or_create_db(salt).await {
-                                        Ok(tags_db) => tags_db,
                                         Err(error) => {
-                                            error!("Failed to open tags database: {}", error);
+                                            let _ = result_sender.send(Err(error.into()));
                                             continue;
                                         }
                                     };
-                                    // TODO: remove unwrap
-                                    utils::spawn_blocking(move || {
-                                        for (tag, index) in tags.iter().zip(first_index..) {
-                                            tags_db.put(tag, index.to_le_bytes())?;
-                                        }
+                                    in_flight_reads.push(utils::spawn_blocking(move || {
+                                        let mut iter = tags_db.raw_iterator();
+
+                                        let mut solutions: Vec<(Tag, u64)> = Vec::new();
+
+                                        let result: Result<_, PlotError> = try {
+                                            if is_lower_overflowed || is_upper_overflowed {
+                                                if let Some(wrapped_start) = wrapped_start {
+                                                    iter.seek(wrapped_start.to_be_bytes());
+                                                    while let Some(tag) = iter.key() {
+                                                        if result_sender.is_canceled() {
+                                                            return;
+                                                        }
+                                                        let (tag, index) =
+                                                            decode_tag_entry(tag, iter.value())?;
+                                                        if u64::from_be_bytes(tag) <= upper {
+                                                            solutions.push((tag, index));
+                                                            iter.next();
+                                                        } else {
+                                                            break;
+                                                        }
+                                                    }
+                                                }
+                                                if let Some(start) = start {
+                                                    iter.seek(start.to_be_bytes());
+                                                    while let Some(tag) = iter.key() {
+                                                        if result_sender.is_canceled() {
+                                                            return;
+                                                        }
+                                                        let (tag, index) =
+                                                            decode_tag_entry(tag, iter.value())?;
+
+                                                        solutions.push((tag, index));
+                                                        iter.next();
+                                                    }
+                                                }
+                                            } else if let Some(start) = start {
+                                                iter.seek(start.to_be_bytes());
+                                                while let Some(tag) = iter.key() {
+                                                    if result_sender.is_canceled() {
+                                                        return;
+                                                    }
+                                                    let (tag, index) =
+                                                        decode_tag_entry(tag, iter.value())?;
+                                                    if u64::from_be_bytes(tag) <= upper {
+                                                        solutions.push((tag, index));
+                                                        iter.next();
+                                                    } else {
+                                                        break;
+                                                    }
+                                                }
+                                            }
 
-                                        Ok::<(), rocksdb::Error>(())
-                                    })
-                                    .await
-                                    .unwrap();
-                                },
-                            );
-                        }
-                        Ok(Some(WriteRequests::FinishCommitmentCreation {
-                            salt,
-                            result_sender,
-                        })) => {
-                            if let Err(error) = tags_dbs.finish_commitment_creation(salt).await {
-                                error!("Failed to finish commitment creation: {}", error);
-                                continue;
-                            }
+                                            iter.status().map_err(CommitmentError::RocksDb)?;
 
-                            let _ = result_sender.send(());
-                        }
-                        Ok(Some(WriteRequests::RemoveCommitment {
-                            salt,
-                            result_sender,
-                        })) => {
-                            if let Err(error) = tags_dbs.remove_commitment(salt).await {
-                                error!("Failed to remove commitment: {}", error);
-                                continue;
-                            }
+                                            solutions.into_iter().next()
+                                        };
 
-                            let _ = result_sender.send(());
-                        }
-                        Ok(None) => {
-                            break 'outer;
+                                        let _ = result_sender.send(result);
+                                    }));
+                                }
+                            }
                         }
-                        Err(_) => {
-                            // Ignore
+                        Request::Write(write_request) => {
+                            write_queue_depth.fetch_sub(1, Ordering::AcqRel);
+                            // Reads dispatched before the write must observe plot before it
+                            while in_flight_reads.next().await.is_some() {}
+
+                            match write_request {
+                                WriteRequests::WriteEncodings {
+                                    encodings,
+                                    first_index,
+                                    result_sender,
+                                } => {
+                                    let _ = result_sender.send(
+                                        try {
+                                            // Aligned such that it can be written with direct I/O
+                                            let mut whole_encoding =
+                                                AlignedBuffer::zeroed(PIECE_SIZE * encodings.len());
+                                            for (chunk, encoding) in whole_encoding
+                                                .chunks_exact_mut(PIECE_SIZE)
+                                                .zip(&encodings)
+                                            {
+                                                chunk.copy_from_slice(encoding);
+                                            }
+                                            let sync = match sync_interval {
+                                                Some(sync_interval) => {
+                                                    unsynced_bytes += whole_encoding.len() as u64;
+                                                    unsynced_bytes >= sync_interval
+                                                }
+                                                None => false,
+                                            };
+                                            if sync {
+                                                unsynced_bytes = 0;
+                                            }
+                                            let plot_file = Arc::clone(&plot_file);
+                                            let write_file = direct_file
+                                                .as_ref()
+                                                .map(Arc::clone)
+                                                .unwrap_or_else(|| Arc::clone(&plot_file));
+                                            utils::spawn_blocking(move || {
+                                                write_file.write_all_at(
+                                                    &whole_encoding,
+                                                    first_index * PIECE_SIZE as u64,
+                                                )?;
+                                                if sync {
+                                                    trace!("Syncing plot file");
+                                                    plot_file.sync_data()?;
+                                                }
+
+                                                Ok::<(), io::Error>(())
+                                            })
+                                            .await?;
+                                            piece_count.fetch_max(
+                                                first_index + encodings.len() as u64,
+                                                Ordering::AcqRel,
+                                            );
+                                        },
+                                    );
+                                }
+                                WriteRequests::WriteTags {
+                                    first_index,
+                                    tags,
+                                    salt,
+                                    result_sender,
+                                } => {
+                                    // Index no longer reflects commitment once it is modified
+                                    tag_indexes.remove(&salt);
+                                    let _ = result_sender.send(
+                                        try {
+                                            let tags_db = tags_dbs.get_or_create_db(salt).await?;
+                                            utils::spawn_blocking(move || {
+                                                for (tag, index) in tags.iter().zip(first_index..) {
+                                                    tags_db.put(tag, index.to_le_bytes())?;
+                                                }
+
+                                                Ok::<(), rocksdb::Error>(())
+                                            })
+                                            .await
+                                            .map_err(CommitmentError::RocksDb)?;
+                                        },
+                                    );
+                                }
+                                WriteRequests::FinishCommitmentCreation {
+                                    salt,
+                                    result_sender,
+                                } => {
+                                    let _ = result_sender.send(
+                                        try {
+                                            tags_dbs.finish_commitment_creation(salt).await?;
+                                            if tag_index {
+                                                let tags_db =
+                                                    tags_dbs.get_or_create_db(salt).await?;
+                                                tag_indexes.build(
+                                                    salt,
+                                                    tags_db,
+                                                    piece_count.load(Ordering::Acquire),
+                                                );
+                                            }
+                                        },
+                                    );
+                                }
+                                WriteRequests::RemoveCommitment {
+                                    salt,
+                                    result_sender,
+                                } => {
+                                    tag_indexes.remove(&salt);
+                                    let _ = result_sender.send(
+                                        tags_dbs
+                                            .remove_commitment(salt)
+                                            .await
+                                            .map_err(PlotError::from),
+                                    );
+                                }
+                            }
                         }
                     }
                 }
 
-                if let Err(error) = plot_file.sync_all().await {
-                    error!("Failed to sync plot file before exit: {}", error);
+                while in_flight_reads.next().await.is_some() {}
+                // Stops index builds, such that they don't hold databases open
+                tag_indexes.clear();
+
+                if !read_only {
+                    if let Err(error) = utils::spawn_blocking(move || plot_file.sync_all()).await {
+                        error!("Failed to sync plot file before exit: {}", error);
+                    }
                 }
 
                 std::thread::spawn({
@@ -380,6 +720,7 @@ impl Plot {
 
                     move || {
                         drop(tags_dbs);
+                        drop(lock);
 
                         handlers.close.call_simple();
                     }
@@ -389,11 +730,17 @@ impl Plot {
 
         let inner = Inner {
             handlers,
-            any_requests_sender,
-            read_requests_sender,
-            write_requests_sender,
+            read_only,
+            mapping,
+            batch_size,
+            requests_sender,
             piece_count,
+            read_queue_depth,
+            write_queue_depth,
+            canceled_requests,
             commitment_statuses: Mutex::new(commitment_statuses),
+            commitment_progress: Mutex::default(),
+            tag_indexes,
         };
 
         Ok(Plot {
@@ -402,126 +749,161 @@ impl Plot {
     }
 
     /// Whether plot doesn't have anything in it
-    pub(crate) async fn is_empty(&self) -> bool {
+    pub async fn is_empty(&self) -> bool {
         self.inner.piece_count.load(Ordering::Acquire) == 0
     }
 
+    /// Number of pieces in plot
+    pub fn piece_count(&self) -> u64 {
+        self.inner.piece_count.load(Ordering::Acquire)
+    }
+
     /// Reads a piece from plot by index
-    pub(crate) async fn read(&self, index: u64) -> io::Result<Piece> {
+    pub async fn read(&self, index: u64) -> Result<Piece, PlotError> {
+        if let Some(mapping) = &self.inner.mapping {
+            return Ok(mapping.read(index, 1)?.as_slice().try_into().unwrap());
+        }
+
         let (result_sender, result_receiver) = oneshot::channel();
 
-        self.inner
-            .read_requests_sender
-            .clone()
-            .send(ReadRequests::ReadEncoding {
-                index,
-                result_sender,
-            })
-            .await
-            .map_err(|error| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Failed sending read encoding request: {}", error),
-                )
-            })?;
-
-        // If fails - it is either full or disconnected, we don't care either way, so ignore result
-        let _ = self.inner.any_requests_sender.clone().try_send(());
-
-        result_receiver.await.map_err(|error| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Read encoding result sender was dropped: {}", error),
-            )
-        })?
+        self.send_read_request(ReadRequests::ReadEncoding {
+            index,
+            result_sender,
+        })
+        .await?;
+
+        result_receiver.await.map_err(|_| PlotError::Closed)?
     }
 
     /// Find pieces within specified solution range.
     ///
-    /// Returns tag and piece index.
-    pub(crate) async fn find_by_range(
+    /// Returns tag and piece index. Dropping returned future cancels the request: it is skipped if
+    /// it wasn't started yet and search is aborted otherwise.
+    pub async fn find_by_range(
         &self,
         target: [u8; 8],
         range: u64,
         salt: Salt,
-    ) -> io::Result<Option<(Tag, u64)>> {
+    ) -> Result<Option<(Tag, u64)>, PlotError> {
         let (result_sender, result_receiver) = oneshot::channel();
 
-        self.inner
-            .read_requests_sender
-            .clone()
-            .send(ReadRequests::FindByRange {
-                target,
-                range,
-                salt,
-                result_sender,
-            })
+        self.send_read_request(ReadRequests::FindByRange {
+            target,
+            range,
+            salt,
+            result_sender,
+        })
+        .await?;
+
+        result_receiver.await.map_err(|_| PlotError::Closed)?
+    }
+
+    /// Same as [`Plot::find_by_range`], but request is canceled and
+    /// [`PlotError::DeadlineExceeded`] is returned if it doesn't complete before `deadline`
+    pub async fn find_by_range_until(
+        &self,
+        target: [u8; 8],
+        range: u64,
+        salt: Salt,
+        deadline: Instant,
+    ) -> Result<Option<(Tag, u64)>, PlotError> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.as_nanos() == 0 {
+            return Err(PlotError::DeadlineExceeded);
+        }
+        future::timeout(timeout, self.find_by_range(target, range, salt))
             .await
-            .map_err(|error| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Failed sending get by range request: {}", error),
-                )
-            })?;
-
-        // If fails - it is either full or disconnected, we don't care either way, so ignore result
-        let _ = self.inner.any_requests_sender.clone().try_send(());
-
-        result_receiver.await.map_err(|error| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Get by range result sender was dropped: {}", error),
-            )
-        })?
+            .map_err(|_| PlotError::DeadlineExceeded)?
     }
 
     /// Writes a piece to the plot by index, will overwrite if piece exists (updates)
-    pub(crate) async fn write_many(
+    pub async fn write_many(
         &self,
         encodings: Vec<Piece>,
         first_index: u64,
-    ) -> io::Result<()> {
+    ) -> Result<(), PlotError> {
         if encodings.is_empty() {
             return Ok(());
         }
         let (result_sender, result_receiver) = oneshot::channel();
 
-        self.inner
-            .write_requests_sender
-            .clone()
-            .send(WriteRequests::WriteEncodings {
-                encodings,
-                first_index,
-                result_sender,
+        self.send_write_request(WriteRequests::WriteEncodings {
+            encodings,
+            first_index,
+            result_sender,
+        })
+        .await?;
+
+        result_receiver.await.map_err(|_| PlotError::Closed)?
+    }
+
+    /// Statuses of all commitments known to the plot
+    pub fn commitment_statuses(&self) -> HashMap<Salt, CommitmentStatus> {
+        self.inner.commitment_statuses.lock().unwrap().clone()
+    }
+
+    /// Number of pieces committed so far for all commitments known to the plot (equals to piece
+    /// count for created commitments)
+    pub fn commitment_progress(&self) -> HashMap<Salt, u64> {
+        let piece_count = self.piece_count();
+        let commitment_progress = self.inner.commitment_progress.lock().unwrap();
+
+        self.commitment_statuses()
+            .into_iter()
+            .map(|(salt, status)| {
+                let progress = match status {
+                    CommitmentStatus::Created => piece_count,
+                    CommitmentStatus::InProgress | CommitmentStatus::Aborted => {
+                        commitment_progress.get(&salt).copied().unwrap_or_default()
+                    }
+                };
+
+                (salt, progress)
             })
-            .await
-            .map_err(|error| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Failed sending write many request: {}", error),
-                )
-            })?;
-
-        // If fails - it is either full or disconnected, we don't care either way, so ignore result
-        let _ = self.inner.any_requests_sender.clone().try_send(());
-
-        result_receiver.await.map_err(|error| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Write many result sender was dropped: {}", error),
-            )
-        })?
+            .collect()
     }
 
-    // Remove all commitments for all salts except those in the list
-    pub(crate) async fn retain_commitments(&self, salts: Vec<Salt>) -> io::Result<()> {
+    /// Number of read requests waiting to be handled
+    pub fn read_queue_depth(&self) -> u64 {
+        self.inner.read_queue_depth.load(Ordering::Acquire)
+    }
+
+    /// Number of write requests waiting to be handled
+    pub fn write_queue_depth(&self) -> u64 {
+        self.inner.write_queue_depth.load(Ordering::Acquire)
+    }
+
+    /// Number of read requests that were skipped because caller stopped waiting for them before
+    /// they were dispatched
+    pub fn canceled_requests(&self) -> u64 {
+        self.inner.canceled_requests.load(Ordering::Acquire)
+    }
+
+    /// Time requests of each priority spent waiting in plot's queues so far, see
+    /// [`RequestPriority`]
+    pub fn queue_wait_stats(&self) -> HashMap<RequestPriority, QueueWaitStats> {
+        self.inner.requests_sender.wait_stats()
+    }
+
+    /// Whether in-memory index of tags of commitment for specified salt is built, see
+    /// [`PlotOptions::tag_index`]
+    pub fn has_tag_index(&self, salt: Salt) -> bool {
+        self.inner.tag_indexes.get(&salt).is_some()
+    }
+
+    /// Remove all commitments for all salts except those in the list
+    pub async fn retain_commitments(&self, salts: Vec<Salt>) -> Result<(), PlotError> {
+        self.check_writable()?;
+        // Statuses are updated by `remove_commitment`, such that commitments that are in progress
+        // are aborted rather than re-created by creation that is still running
         let salts: Vec<Salt> = self
             .inner
             .commitment_statuses
             .lock()
             .unwrap()
-            .drain_filter(|salt, _status| !salts.contains(salt))
-            .map(|(salt, _status)| salt)
+            .keys()
+            .filter(|salt| !salts.contains(salt))
+            .copied()
             .collect();
 
         for salt in salts {
@@ -531,7 +913,10 @@ impl Plot {
         Ok(())
     }
 
-    pub(crate) async fn create_commitment(&self, salt: Salt) -> io::Result<()> {
+    /// Create commitment (tags) for the whole plot using specified salt, does nothing if such
+    /// commitment already exists
+    pub async fn create_commitment(&self, salt: Salt) -> Result<(), PlotError> {
+        self.check_writable()?;
         {
             let mut commitment_statuses = self.inner.commitment_statuses.lock().unwrap();
             if let Some(CommitmentStatus::Created) = commitment_statuses.get(&salt) {
@@ -540,13 +925,14 @@ impl Plot {
             commitment_statuses.insert(salt, CommitmentStatus::InProgress);
         }
         let piece_count = self.inner.piece_count.load(Ordering::Acquire);
-        for batch_start in (0..piece_count).step_by(BATCH_SIZE as usize) {
+        let batch_size = self.inner.batch_size;
+        for batch_start in (0..piece_count).step_by(batch_size as usize) {
             if let Some(CommitmentStatus::Aborted) =
                 self.inner.commitment_statuses.lock().unwrap().get(&salt)
             {
                 break;
             }
-            let pieces_to_process = (batch_start + BATCH_SIZE).min(piece_count) - batch_start;
+            let pieces_to_process = (batch_start + batch_size).min(piece_count) - batch_start;
             let pieces = self.read_pieces(batch_start, pieces_to_process).await?;
 
             let tags: Vec<Tag> = utils::spawn_blocking(move || {
@@ -557,36 +943,56 @@ impl Plot {
             })
             .await;
 
-            let (result_sender, result_receiver) = oneshot::channel();
-
-            self.inner
-                .write_requests_sender
-                .clone()
-                .send(WriteRequests::WriteTags {
-                    first_index: batch_start,
-                    tags,
-                    salt,
-                    result_sender,
-                })
-                .await
-                .map_err(|error| {
-                    io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Failed sending write tags request: {}", error),
-                    )
-                })?;
+            self.send_tags(salt, batch_start, tags).await?;
+        }
+
+        self.finish_commitment(salt).await
+    }
 
-            // If fails - it is either full or disconnected, we don't care either way, so ignore result
-            let _ = self.inner.any_requests_sender.clone().try_send(());
+    /// Write tags of pieces starting at `first_index` to commitment for specified salt, commitment
+    /// is marked as in progress if it isn't already.
+    ///
+    /// This allows to create commitment in the same pass as plotting instead of reading plot back
+    /// with [`Plot::create_commitment`] afterwards, commitment must be finished with
+    /// [`Plot::finish_commitment`] once tags for all pieces are written. Returns
+    /// [`PlotError::CommitmentAborted`] if commitment was removed in the meantime.
+    pub async fn write_tags(
+        &self,
+        salt: Salt,
+        first_index: u64,
+        tags: Vec<Tag>,
+    ) -> Result<(), PlotError> {
+        self.check_writable()?;
+        let aborted = {
+            let mut commitment_statuses = self.inner.commitment_statuses.lock().unwrap();
+            match commitment_statuses.get(&salt) {
+                Some(CommitmentStatus::Aborted) => {
+                    commitment_statuses.remove(&salt);
+                    true
+                }
+                Some(CommitmentStatus::InProgress) => false,
+                Some(CommitmentStatus::Created) | None => {
+                    commitment_statuses.insert(salt, CommitmentStatus::InProgress);
+                    false
+                }
+            }
+        };
+
+        if aborted {
+            self.inner.commitment_progress.lock().unwrap().remove(&salt);
+            self.remove_commitment(salt).await?;
 
-            result_receiver.await.map_err(|error| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Write tags result sender was dropped: {}", error),
-                )
-            })??;
+            return Err(PlotError::CommitmentAborted);
         }
 
+        self.send_tags(salt, first_index, tags).await
+    }
+
+    /// Mark commitment for specified salt that was written with [`Plot::write_tags`] as created
+    pub async fn finish_commitment(&self, salt: Salt) -> Result<(), PlotError> {
+        self.check_writable()?;
+        self.inner.commitment_progress.lock().unwrap().remove(&salt);
+
         let aborted = {
             let mut commitment_statuses = self.inner.commitment_statuses.lock().unwrap();
             if let Some(CommitmentStatus::Aborted) = commitment_statuses.get(&salt) {
@@ -600,44 +1006,18 @@ impl Plot {
         if aborted {
             self.remove_commitment(salt).await?;
 
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Commitment creation was aborted",
-            ));
+            return Err(PlotError::CommitmentAborted);
         }
 
         let (result_sender, result_receiver) = oneshot::channel();
 
-        self.inner
-            .write_requests_sender
-            .clone()
-            .send(WriteRequests::FinishCommitmentCreation {
-                salt,
-                result_sender,
-            })
-            .await
-            .map_err(|error| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!(
-                        "Failed sending finish commitment creation request: {}",
-                        error
-                    ),
-                )
-            })?;
-
-        // If fails - it is either full or disconnected, we don't care either way, so ignore result
-        let _ = self.inner.any_requests_sender.clone().try_send(());
-
-        result_receiver.await.map_err(|error| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Finish commitment creation result sender was dropped: {}",
-                    error
-                ),
-            )
-        })?;
+        self.send_write_request(WriteRequests::FinishCommitmentCreation {
+            salt,
+            result_sender,
+        })
+        .await?;
+
+        result_receiver.await.map_err(|_| PlotError::Closed)??;
 
         let aborted = {
             let mut commitment_statuses = self.inner.commitment_statuses.lock().unwrap();
@@ -653,16 +1033,41 @@ impl Plot {
         if aborted {
             self.remove_commitment(salt).await?;
 
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Commitment creation was aborted",
-            ));
+            return Err(PlotError::CommitmentAborted);
         }
 
         Ok(())
     }
 
-    pub(crate) async fn remove_commitment(&self, salt: Salt) -> io::Result<()> {
+    async fn send_tags(
+        &self,
+        salt: Salt,
+        first_index: u64,
+        tags: Vec<Tag>,
+    ) -> Result<(), PlotError> {
+        let last_index = first_index + tags.len() as u64;
+        let (result_sender, result_receiver) = oneshot::channel();
+
+        self.send_write_request(WriteRequests::WriteTags {
+            first_index,
+            tags,
+            salt,
+            result_sender,
+        })
+        .await?;
+
+        result_receiver.await.map_err(|_| PlotError::Closed)??;
+
+        let mut commitment_progress = self.inner.commitment_progress.lock().unwrap();
+        let progress = commitment_progress.entry(salt).or_default();
+        *progress = last_index.max(*progress);
+
+        Ok(())
+    }
+
+    /// Remove commitment for specified salt, in-progress commitment creation is aborted
+    pub async fn remove_commitment(&self, salt: Salt) -> Result<(), PlotError> {
+        self.check_writable()?;
         {
             let mut commitment_statuses = self.inner.commitment_statuses.lock().unwrap();
             if let Entry::Occupied(mut entry) = commitment_statuses.entry(salt) {
@@ -682,67 +1087,91 @@ impl Plot {
 
         let (result_sender, result_receiver) = oneshot::channel();
 
-        self.inner
-            .write_requests_sender
-            .clone()
-            .send(WriteRequests::RemoveCommitment {
-                salt,
-                result_sender,
-            })
-            .await
-            .map_err(|error| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Failed sending remove tags request: {}", error),
-                )
-            })?;
-
-        // If fails - it is either full or disconnected, we don't care either way, so ignore result
-        let _ = self.inner.any_requests_sender.clone().try_send(());
-
-        result_receiver.await.map_err(|error| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Remove tags result sender was dropped: {}", error),
-            )
+        self.send_write_request(WriteRequests::RemoveCommitment {
+            salt,
+            result_sender,
         })
+        .await?;
+
+        result_receiver.await.map_err(|_| PlotError::Closed)?
     }
 
     /// Run callback when plot is closed, can be used to handle graceful shutdown since plot will be
     /// closed on drop asynchronously and thus requires extra care to be handled properly.
-    pub(crate) fn on_close<F: FnOnce() + Send + 'static>(&self, callback: F) -> HandlerId {
+    pub fn on_close<F: FnOnce() + Send + 'static>(&self, callback: F) -> HandlerId {
         self.inner.handlers.close.add(Box::new(callback))
     }
 
-    /// Returns pieces packed one after another in contiguous `Vec<u8>`
-    async fn read_pieces(&self, first_index: u64, count: u64) -> io::Result<Vec<u8>> {
+    /// Returns pieces packed one after another in contiguous `Vec<u8>`, dropping returned future
+    /// cancels the request if it wasn't started yet
+    pub async fn read_pieces(&self, first_index: u64, count: u64) -> Result<Vec<u8>, PlotError> {
+        if self.inner.mapping.is_some() {
+            let inner = Arc::clone(&self.inner);
+            return utils::spawn_blocking(move || {
+                inner.mapping.as_ref().unwrap().read(first_index, count)
+            })
+            .await
+            .map_err(PlotError::from);
+        }
+
         let (result_sender, result_receiver) = oneshot::channel();
 
-        self.inner
-            .read_requests_sender
-            .clone()
-            .send(ReadRequests::ReadEncodings {
-                first_index,
-                count,
-                result_sender,
-            })
+        self.send_read_request(ReadRequests::ReadEncodings {
+            first_index,
+            count,
+            result_sender,
+        })
+        .await?;
+
+        result_receiver.await.map_err(|_| PlotError::Closed)?
+    }
+
+    async fn send_read_request(&self, request: ReadRequests) -> Result<(), PlotError> {
+        self.inner.read_queue_depth.fetch_add(1, Ordering::AcqRel);
+        let request = Request::Read(request);
+        if self
+            .inner
+            .requests_sender
+            .send(request.priority(), request)
             .await
-            .map_err(|error| {
-                io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Failed sending read encodings request: {}", error),
-                )
-            })?;
-
-        // If fails - it is either full or disconnected, we don't care either way, so ignore result
-        let _ = self.inner.any_requests_sender.clone().try_send(());
-
-        result_receiver.await.map_err(|error| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("Read encodings result sender was dropped: {}", error),
-            )
-        })?
+            .is_err()
+        {
+            self.inner.read_queue_depth.fetch_sub(1, Ordering::AcqRel);
+            return Err(PlotError::Closed);
+        }
+
+        Ok(())
+    }
+
+    /// Whether plot was opened in read-only mode
+    pub fn is_read_only(&self) -> bool {
+        self.inner.read_only
+    }
+
+    fn check_writable(&self) -> Result<(), PlotError> {
+        if self.inner.read_only {
+            return Err(PlotError::ReadOnly);
+        }
+
+        Ok(())
+    }
+
+    async fn send_write_request(&self, request: WriteRequests) -> Result<(), PlotError> {
+        self.check_writable()?;
+        self.inner.write_queue_depth.fetch_add(1, Ordering::AcqRel);
+        let request = Request::Write(request);
+        if self
+            .inner
+            .requests_sender
+            .send(request.priority(), request)
+            .await
+            .is_err()
+        {
+            self.inner.write_queue_depth.fetch_sub(1, Ordering::AcqRel);
+            return Err(PlotError::Closed);
+        }
+
+        Ok(())
     }
 }
 
@@ -794,6 +1223,7 @@ mod tests {
     }
 
     #[async_std::test]
+    #[allow(clippy::bool_assert_comparison)]
     async fn test_read_write() {
         init();
         let path = TargetDirectory::new("read_write");
@@ -811,9 +1241,13 @@ mod tests {
 
         assert_eq!(piece[..], extracted_piece[..]);
 
+        // Data directory is locked until plot is closed
+        let (tx, rx) = oneshot::channel();
+        let _handler = plot.on_close(move || {
+            let _ = tx.send(());
+        });
         drop(plot);
-
-        async_std::task::sleep(Duration::from_millis(100)).await;
+        rx.await.unwrap();
 
         // Make sure it is still not empty on reopen
         let plot = Plot::open_or_create(&path).await.unwrap();
@@ -825,6 +1259,233 @@ mod tests {
         async_std::task::sleep(Duration::from_millis(100)).await;
     }
 
+    #[async_std::test]
+    async fn test_concurrent_reads() {
+        init();
+        let path = TargetDirectory::new("concurrent_reads");
+
+        let pieces = (0..64).map(|_| generate_random_piece()).collect::<Vec<_>>();
+        let plot = Plot::open_or_create(&path).await.unwrap();
+        plot.write_many(pieces.clone(), 0).await.unwrap();
+
+        let bulk_reads = (0..4).map(|_| plot.read_pieces(0, pieces.len() as u64));
+        let single_reads = (0..pieces.len() as u64).map(|index| plot.read(index));
+        let (bulk_reads, single_reads) = futures::future::join(
+            futures::future::join_all(bulk_reads),
+            futures::future::join_all(single_reads),
+        )
+        .await;
+
+        for bulk_read in bulk_reads {
+            assert_eq!(bulk_read.unwrap(), pieces.concat());
+        }
+        for (single_read, piece) in single_reads.into_iter().zip(&pieces) {
+            assert_eq!(single_read.unwrap()[..], piece[..]);
+        }
+
+        // Writes after reads are still applied in order
+        let piece = generate_random_piece();
+        let (read, write) =
+            futures::future::join(plot.read(0), plot.write_many(vec![piece], 0)).await;
+        assert_eq!(read.unwrap()[..], pieces[0][..]);
+        write.unwrap();
+        assert_eq!(plot.read(0).await.unwrap()[..], piece[..]);
+
+        drop(plot);
+
+        async_std::task::sleep(Duration::from_millis(100)).await;
+    }
+
+    #[async_std::test]
+    async fn test_read_not_blocked_by_bulk_reads() {
+        init();
+        let path = TargetDirectory::new("read_not_blocked_by_bulk_reads");
+
+        let piece_count = 4096;
+        let pieces = (0..piece_count)
+            .map(|_| generate_random_piece())
+            .collect::<Vec<_>>();
+        let plot = Plot::open_or_create(&path).await.unwrap();
+        plot.write_many(pieces.clone(), 0).await.unwrap();
+
+        // Single read is dispatched after bulk reads, but doesn't wait for them to complete
+        let bulk_reads =
+            futures::future::join_all((0..8).map(|_| plot.read_pieces(0, piece_count as u64)));
+        let single_read = plot.read(piece_count as u64 - 1);
+        match futures::future::select(Box::pin(bulk_reads), Box::pin(single_read)).await {
+            futures::future::Either::Left(_) => {
+                panic!("Single read must complete before bulk reads");
+            }
+            futures::future::Either::Right((single_read, bulk_reads)) => {
+                assert_eq!(single_read.unwrap()[..], pieces[piece_count - 1][..]);
+                for bulk_read in bulk_reads.await {
+                    assert_eq!(bulk_read.unwrap().len(), piece_count * PIECE_SIZE);
+                }
+            }
+        }
+
+        drop(plot);
+
+        async_std::task::sleep(Duration::from_millis(100)).await;
+    }
+
+    #[async_std::test]
+    async fn test_batch_size() {
+        init();
+
+        for &(batch_size, batches) in &[(BATCH_SIZE, 1), (3, 3), (1, 8)] {
+            let path = TargetDirectory::new(&format!("batch_size_{}", batch_size));
+            let plot = Plot::open_with_options(
+                &path,
+                PlotOptions {
+                    batch_size,
+                    ..PlotOptions::default()
+                },
+            )
+            .await
+            .unwrap();
+            let pieces = (0..8).map(|_| generate_random_piece()).collect::<Vec<_>>();
+            plot.write_many(pieces, 0).await.unwrap();
+            plot.create_commitment([1u8; 8]).await.unwrap();
+
+            // Commitment is created by reading one batch at a time
+            assert_eq!(
+                plot.queue_wait_stats()[&RequestPriority::CommitmentRead].requests,
+                batches
+            );
+
+            let (tx, rx) = oneshot::channel();
+            let _handler = plot.on_close(move || {
+                let _ = tx.send(());
+            });
+            drop(plot);
+            rx.await.unwrap();
+        }
+    }
+
+    #[async_std::test]
+    async fn test_mmap() {
+        init();
+        let path = TargetDirectory::new("mmap");
+
+        let plot = Plot::open_with_options(
+            &path,
+            PlotOptions {
+                mmap: true,
+                ..PlotOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(plot.read(0).await.is_err());
+
+        let pieces = (0..4).map(|_| generate_random_piece()).collect::<Vec<_>>();
+        plot.write_many(pieces[..2].to_vec(), 0).await.unwrap();
+        assert_eq!(plot.read(1).await.unwrap()[..], pieces[1][..]);
+
+        // Plot grows after it was mapped
+        plot.write_many(pieces[2..].to_vec(), 2).await.unwrap();
+        assert_eq!(plot.read(3).await.unwrap()[..], pieces[3][..]);
+        assert_eq!(plot.read_pieces(0, 4).await.unwrap(), pieces.concat());
+        assert!(plot.read_pieces(3, 2).await.is_err());
+
+        drop(plot);
+
+        async_std::task::sleep(Duration::from_millis(100)).await;
+
+        // Read-only plot is not locked and is read without mapping
+        let plot = Plot::open_with_options(
+            &path,
+            PlotOptions {
+                read_only: true,
+                mmap: true,
+                ..PlotOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert!(plot.inner.mapping.is_none());
+        assert_eq!(plot.read(3).await.unwrap()[..], pieces[3][..]);
+    }
+
+    #[async_std::test]
+    async fn test_mmap_truncated() {
+        init();
+        let path = TargetDirectory::new("mmap_truncated");
+
+        let plot = Plot::open_with_options(
+            &path,
+            PlotOptions {
+                mmap: true,
+                ..PlotOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        let pieces = (0..4).map(|_| generate_random_piece()).collect::<Vec<_>>();
+        plot.write_many(pieces.clone(), 0).await.unwrap();
+        assert_eq!(plot.read(3).await.unwrap()[..], pieces[3][..]);
+
+        // Plot file truncated underneath the mapping results in error instead of SIGBUS
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path.join("plot.bin"))
+            .unwrap()
+            .set_len(PIECE_SIZE as u64)
+            .unwrap();
+        assert!(matches!(plot.read(3).await, Err(PlotError::Io(_))));
+        assert!(matches!(
+            plot.read_pieces(0, 4).await,
+            Err(PlotError::Io(_))
+        ));
+        // What is left in the file is read directly
+        assert_eq!(plot.read(0).await.unwrap()[..], pieces[0][..]);
+    }
+
+    #[async_std::test]
+    async fn test_direct_io() {
+        init();
+        let path = TargetDirectory::new("direct_io");
+
+        let plot = Plot::open_with_options(
+            &path,
+            PlotOptions {
+                direct_io: true,
+                sync_interval: Some(2 * PIECE_SIZE as u64),
+                preallocate: Some(8),
+                ..PlotOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let pieces = (0..3).map(|_| generate_random_piece()).collect::<Vec<_>>();
+        plot.write_many(pieces[..1].to_vec(), 0).await.unwrap();
+        plot.write_many(pieces[1..].to_vec(), 1).await.unwrap();
+        assert_eq!(plot.read_pieces(0, 3).await.unwrap(), pieces.concat());
+
+        // Space is reserved, but plot size only reflects written pieces
+        let metadata = std::fs::metadata(path.join("plot.bin")).unwrap();
+        assert_eq!(metadata.len(), 3 * PIECE_SIZE as u64);
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+
+            assert!(metadata.blocks() * 512 >= 8 * PIECE_SIZE as u64);
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let _handler = plot.on_close(move || {
+            let _ = tx.send(());
+        });
+        drop(plot);
+        rx.await.unwrap();
+
+        let plot = Plot::open_or_create(&path).await.unwrap();
+        assert_eq!(plot.piece_count(), 3);
+        assert_eq!(plot.read(2).await.unwrap()[..], pieces[2][..]);
+    }
+
     #[async_std::test]
     async fn test_commitment() {
         init();
@@ -847,6 +1508,278 @@ mod tests {
             .unwrap()
             .unwrap();
         assert_eq!(correct_tag, tag);
+
+        // Request is not even sent once deadline has passed
+        assert!(matches!(
+            plot.find_by_range_until(correct_tag, solution_range, salt, Instant::now())
+                .await,
+            Err(PlotError::DeadlineExceeded)
+        ));
+
+        // Request whose caller went away before it was dispatched is skipped
+        let (result_sender, result_receiver) = oneshot::channel();
+        drop(result_receiver);
+        plot.send_read_request(ReadRequests::ReadEncoding {
+            index,
+            result_sender,
+        })
+        .await
+        .unwrap();
+        // Requests of the same priority are dispatched in order, so canceled one was handled
+        // before this one completes
+        assert!(plot
+            .find_by_range(correct_tag, solution_range, salt)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(plot.canceled_requests(), 1);
+
+        plot.remove_commitment(salt).await.unwrap();
+        assert!(plot.commitment_statuses().is_empty());
+
+        let (tx, rx) = oneshot::channel();
+        let _handler = plot.on_close(move || {
+            let _ = tx.send(());
+        });
+        drop(plot);
+        rx.await.unwrap();
+
+        // Removed commitment must not come back after reopening
+        let plot = Plot::open_or_create(&path).await.unwrap();
+        assert!(plot.commitment_statuses().is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_corrupt_commitment() {
+        init();
+        let path = TargetDirectory::new("corrupt_commitment");
+        let salt: Salt = [1u8; 8];
+
+        let plot = Plot::open_or_create(&path).await.unwrap();
+        plot.write_many(vec![generate_random_piece()], 0)
+            .await
+            .unwrap();
+        plot.create_commitment(salt).await.unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let _handler = plot.on_close(move || {
+            let _ = tx.send(());
+        });
+        drop(plot);
+        rx.await.unwrap();
+
+        let db = rocksdb::DB::open_default(path.join("plot-tags").join(hex::encode(salt))).unwrap();
+        db.put([0u8; 9], [0u8; 3]).unwrap();
+        drop(db);
+
+        // Corrupt entry is reported instead of bringing plot down
+        let plot = Plot::open_or_create(&path).await.unwrap();
+        assert!(matches!(
+            plot.find_by_range([0u8; 8], u64::MAX, salt).await,
+            Err(PlotError::Commitments(CommitmentError::CorruptEntry))
+        ));
+        assert_eq!(plot.read(0).await.unwrap().len(), PIECE_SIZE);
+    }
+
+    #[async_std::test]
+    async fn test_read_only() {
+        init();
+        let path = TargetDirectory::new("read_only");
+
+        // Nothing is created for missing plot
+        assert!(Plot::open_read_only(&path).await.is_err());
+        assert_eq!(fs::read_dir(&*path).unwrap().count(), 0);
+
+        let piece = generate_random_piece();
+        let salt: Salt = [1u8; 8];
+        let plot = Plot::open_or_create(&path).await.unwrap();
+        plot.write_many(vec![piece], 0).await.unwrap();
+        plot.create_commitment(salt).await.unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let _handler = plot.on_close(move || {
+            let _ = tx.send(());
+        });
+        drop(plot);
+        rx.await.unwrap();
+
+        fn list_files(path: &std::path::Path) -> Vec<(std::path::PathBuf, u64)> {
+            let mut files = Vec::new();
+            for entry in fs::read_dir(path).unwrap() {
+                let entry = entry.unwrap();
+                if entry.file_type().unwrap().is_dir() {
+                    files.extend(list_files(&entry.path()));
+                } else {
+                    files.push((entry.path(), entry.metadata().unwrap().len()));
+                }
+            }
+            files.sort();
+            files
+        }
+        let files_before = list_files(path.as_ref());
+
+        let plot = Plot::open_read_only(&path).await.unwrap();
+        assert!(plot.is_read_only());
+        assert_eq!(plot.read(0).await.unwrap()[..], piece[..]);
+        let tag = crypto::create_tag(&piece, &salt);
+        assert_eq!(
+            plot.find_by_range(tag, 0, salt).await.unwrap(),
+            Some((tag, 0))
+        );
+        assert_eq!(plot.find_by_range(tag, 0, [2u8; 8]).await.unwrap(), None);
+
+        assert!(matches!(
+            plot.write_many(vec![piece], 1).await,
+            Err(PlotError::ReadOnly)
+        ));
+        assert!(matches!(
+            plot.create_commitment([2u8; 8]).await,
+            Err(PlotError::ReadOnly)
+        ));
+        assert!(matches!(
+            plot.remove_commitment(salt).await,
+            Err(PlotError::ReadOnly)
+        ));
+        assert_eq!(plot.commitment_statuses().len(), 1);
+
+        let (tx, rx) = oneshot::channel();
+        let _handler = plot.on_close(move || {
+            let _ = tx.send(());
+        });
+        drop(plot);
+        rx.await.unwrap();
+
+        assert_eq!(list_files(path.as_ref()), files_before);
+    }
+
+    #[async_std::test]
+    async fn test_lock() {
+        init();
+        let path = TargetDirectory::new("lock");
+
+        let plot = Plot::open_or_create(&path).await.unwrap();
+        plot.write_many(vec![generate_random_piece()], 0)
+            .await
+            .unwrap();
+
+        match Plot::open_or_create(&path).await {
+            Err(PlotError::Locked { pid }) => {
+                assert_eq!(pid, Some(std::process::id()));
+            }
+            _ => panic!("Plot must be locked"),
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let _handler = plot.on_close(move || {
+            let _ = tx.send(());
+        });
+        drop(plot);
+        rx.await.unwrap();
+
+        // Lock is released on close, read-only plot can be opened while plot is used
+        let plot = Plot::open_or_create(&path).await.unwrap();
+        let read_only_plot = Plot::open_read_only(&path).await.unwrap();
+        assert_eq!(read_only_plot.piece_count(), 1);
+
+        let (tx, rx) = oneshot::channel();
+        let _handler = plot.on_close(move || {
+            let _ = tx.send(());
+        });
+        drop(plot);
+        rx.await.unwrap();
+
+        // Read-only plot doesn't take the lock, plot can be opened for writing while it is open
+        let plot = Plot::open_or_create(&path).await.unwrap();
+        drop(read_only_plot);
+        drop(plot);
+
+        async_std::task::sleep(Duration::from_millis(100)).await;
+    }
+
+    #[async_std::test]
+    async fn test_tag_index() {
+        init();
+        let path = TargetDirectory::new("tag_index");
+        let salt: Salt = [1u8; 8];
+        let pieces: Vec<Piece> = (0..1024_usize).map(|_| generate_random_piece()).collect();
+        let mut tags: Vec<u64> = pieces
+            .iter()
+            .map(|piece| u64::from_be_bytes(crypto::create_tag(piece, &salt)))
+            .collect();
+        tags.sort_unstable();
+
+        let plot = Plot::open_with_options(
+            &path,
+            PlotOptions {
+                tag_index: true,
+                ..PlotOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        plot.write_many(pieces, 0).await.unwrap();
+        plot.create_commitment(salt).await.unwrap();
+
+        let wait_for_index = |plot: &Plot| {
+            let plot = plot.clone();
+            async move {
+                for _ in 0..100 {
+                    if plot.has_tag_index(salt) {
+                        return;
+                    }
+                    async_std::task::sleep(Duration::from_millis(10)).await;
+                }
+                panic!("Tag index wasn't built in time");
+            }
+        };
+        wait_for_index(&plot).await;
+
+        // Results must be the same as without index, including ranges without any tags in them
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let target: u64 = rng.gen();
+            let range = rng.gen_range(0..u64::MAX / 256);
+            let lower = target.wrapping_sub(range / 2);
+            let upper = target.wrapping_add(range / 2);
+            let expected = if lower <= upper {
+                tags.iter().find(|&&tag| tag >= lower && tag <= upper)
+            } else {
+                tags.iter()
+                    .find(|&&tag| tag <= upper)
+                    .or_else(|| tags.iter().find(|&&tag| tag >= lower))
+            };
+
+            let solution = plot
+                .find_by_range(target.to_be_bytes(), range, salt)
+                .await
+                .unwrap()
+                .map(|(tag, _index)| u64::from_be_bytes(tag));
+            assert_eq!(solution.as_ref(), expected);
+        }
+
+        // Index is rebuilt when plot is reopened and discarded when commitment is removed
+        let (tx, rx) = oneshot::channel();
+        let _handler = plot.on_close(move || {
+            let _ = tx.send(());
+        });
+        drop(plot);
+        rx.await.unwrap();
+        let plot = Plot::open_with_options(
+            &path,
+            PlotOptions {
+                tag_index: true,
+                ..PlotOptions::default()
+            },
+        )
+        .await
+        .unwrap();
+        wait_for_index(&plot).await;
+        plot.remove_commitment(salt).await.unwrap();
+        assert!(!plot.has_tag_index(salt));
+
+        drop(plot);
+
+        async_std::task::sleep(Duration::from_millis(100)).await;
     }
 
     #[async_std::test]
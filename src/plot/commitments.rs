@@ -2,7 +2,7 @@ use crate::{utils, Salt};
 use async_std::io;
 use async_std::path::PathBuf;
 use log::warn;
-use rocksdb::{DBWithThreadMode, SingleThreaded, DB};
+use rocksdb::{DBWithThreadMode, Options, SingleThreaded, DB};
 use serde::ser::SerializeStruct;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::hash_map::Entry;
@@ -11,12 +11,21 @@ use std::convert::TryInto;
 use std::sync::Arc;
 use thiserror::Error;
 
+/// Errors that happen when working with commitments
 #[derive(Debug, Error)]
-pub(super) enum DbError {
-    #[error("RocksDB database opening error: {0}")]
+pub enum CommitmentError {
+    /// RocksDB error
+    #[error("RocksDB error: {0}")]
     RocksDb(rocksdb::Error),
+    /// Failed to update metadata file
     #[error("Metadata file error: {0}")]
     Metadata(io::Error),
+    /// Failed to remove commitment from disk
+    #[error("Commitment removal error: {0}")]
+    Removal(io::Error),
+    /// Commitment database contains entry that is not a valid tag and piece index
+    #[error("Corrupt commitment entry")]
+    CorruptEntry,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -78,37 +87,66 @@ impl<'de> Deserialize<'de> for Metadata {
     }
 }
 
+/// Read commitments metadata from `metadata.json` in specified directory, returns `None` if it
+/// doesn't exist
+pub(super) fn read_metadata(
+    path: &std::path::Path,
+) -> Result<Option<HashMap<Salt, CommitmentStatus>>, CommitmentError> {
+    let metadata = match std::fs::read_to_string(path.join("metadata.json")) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(None);
+        }
+        Err(error) => {
+            return Err(CommitmentError::Metadata(error));
+        }
+    };
+    let metadata: Metadata = serde_json::from_str(&metadata).map_err(|error| {
+        CommitmentError::Metadata(io::Error::new(io::ErrorKind::InvalidData, error))
+    })?;
+
+    Ok(Some(metadata.commitments))
+}
+
 #[derive(Debug)]
 pub(super) struct Commitments {
     path: PathBuf,
+    read_only: bool,
     databases: HashMap<Salt, Arc<DBWithThreadMode<SingleThreaded>>>,
     metadata: Metadata,
 }
 
 impl Commitments {
-    pub(super) async fn new(path: PathBuf) -> io::Result<Self> {
+    /// Open commitments in specified directory, in read-only mode unfinished commitments from the
+    /// previous run are ignored rather than removed
+    pub(super) async fn new(path: PathBuf, read_only: bool) -> io::Result<Self> {
         let mut metadata: Metadata = async_std::fs::read_to_string(path.join("metadata.json"))
             .await
             .ok()
             .and_then(|metadata| serde_json::from_str(&metadata).ok())
             .unwrap_or_default();
 
-        // Remove unfinished commitments from the previous run
-        for (salt, _status) in metadata
+        let unfinished = metadata
             .commitments
             .drain_filter(|_salt, status| *status != CommitmentStatus::Created)
-        {
-            let commitment_path = path.join(hex::encode(salt));
-            if let Err(error) = async_std::fs::remove_dir_all(&commitment_path).await {
-                warn!(
-                    "Failed to remove commitment at {:?}: {}",
-                    commitment_path, error
-                );
+            .collect::<Vec<_>>();
+
+        if !read_only {
+            // Remove unfinished commitments from the previous run
+            for (salt, _status) in unfinished {
+                let commitment_path = path.join(hex::encode(salt));
+                if let Err(error) = async_std::fs::remove_dir_all(&commitment_path).await {
+                    warn!(
+                        "Failed to remove commitment at {:?}: {}",
+                        commitment_path, error
+                    );
+                }
             }
         }
 
         Ok(Self {
             path,
+            read_only,
             databases: HashMap::new(),
             metadata,
         })
@@ -118,11 +156,47 @@ impl Commitments {
         self.metadata.commitments.keys()
     }
 
+    /// Get database of existing commitment, in read-only mode database is opened read-only and
+    /// `None` is returned for unknown commitments, otherwise behaves like
+    /// [`Commitments::get_or_create_db`]
+    pub(super) async fn get_db(
+        &mut self,
+        salt: Salt,
+    ) -> Result<Option<Arc<DBWithThreadMode<SingleThreaded>>>, CommitmentError> {
+        if !self.read_only {
+            return self.get_or_create_db(salt).await.map(Some);
+        }
+
+        if !self.metadata.commitments.contains_key(&salt) {
+            return Ok(None);
+        }
+
+        match self.databases.entry(salt) {
+            Entry::Occupied(entry) => Ok(Some(Arc::clone(entry.get()))),
+            Entry::Vacant(entry) => {
+                let db_path = self.path.join(hex::encode(salt));
+                let mut options = Options::default();
+                // RocksDB writes info log even in read-only mode, keep it out of data directory
+                options.set_db_log_dir(std::env::temp_dir());
+                options.set_keep_log_file_num(1);
+                let db = Arc::new(
+                    utils::spawn_blocking(move || DB::open_for_read_only(&options, db_path, false))
+                        .await
+                        .map_err(CommitmentError::RocksDb)?,
+                );
+
+                entry.insert(Arc::clone(&db));
+
+                Ok(Some(db))
+            }
+        }
+    }
+
     /// Get existing database or create an empty one with [`CommitmentStatus::InProgress`] status
     pub(super) async fn get_or_create_db(
         &mut self,
         salt: Salt,
-    ) -> Result<Arc<DBWithThreadMode<SingleThreaded>>, DbError> {
+    ) -> Result<Arc<DBWithThreadMode<SingleThreaded>>, CommitmentError> {
         match self.databases.entry(salt) {
             Entry::Occupied(entry) => Ok(Arc::clone(entry.get())),
             Entry::Vacant(entry) => {
@@ -130,7 +204,7 @@ impl Commitments {
                 let db = Arc::new(
                     utils::spawn_blocking(move || DB::open_default(db_path))
                         .await
-                        .map_err(DbError::RocksDb)?,
+                        .map_err(CommitmentError::RocksDb)?,
                 );
 
                 entry.insert(Arc::clone(&db));
@@ -143,7 +217,7 @@ impl Commitments {
                     serde_json::to_string(&self.metadata).unwrap(),
                 )
                 .await
-                .map_err(DbError::Metadata)?;
+                .map_err(CommitmentError::Metadata)?;
 
                 Ok(db)
             }
@@ -152,7 +226,10 @@ impl Commitments {
 
     /// Transition database associated with `salt` to created status, meaning that it represents the
     /// whole plot and not some in-progress partial commitment
-    pub(super) async fn finish_commitment_creation(&mut self, salt: Salt) -> io::Result<()> {
+    pub(super) async fn finish_commitment_creation(
+        &mut self,
+        salt: Salt,
+    ) -> Result<(), CommitmentError> {
         self.metadata
             .commitments
             .insert(salt, CommitmentStatus::Created);
@@ -161,10 +238,11 @@ impl Commitments {
             serde_json::to_string(&self.metadata).unwrap(),
         )
         .await
+        .map_err(CommitmentError::Metadata)
     }
 
     /// Removes commitment from disk
-    pub(super) async fn remove_commitment(&mut self, salt: Salt) -> io::Result<()> {
+    pub(super) async fn remove_commitment(&mut self, salt: Salt) -> Result<(), CommitmentError> {
         self.metadata.commitments.remove(&salt);
         let db_path = self.path.join(hex::encode(salt));
         let database = self.databases.remove(&salt);
@@ -172,8 +250,14 @@ impl Commitments {
             drop(database);
             std::fs::remove_dir_all(db_path)
         })
-        .await?;
+        .await
+        .map_err(CommitmentError::Removal)?;
 
-        Ok(())
+        async_std::fs::write(
+            self.path.join("metadata.json"),
+            serde_json::to_string(&self.metadata).unwrap(),
+        )
+        .await
+        .map_err(CommitmentError::Metadata)
     }
 }
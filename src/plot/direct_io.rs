@@ -0,0 +1,134 @@
+use log::warn;
+use std::alloc::{self, Layout};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::slice;
+
+/// Alignment of buffers, offsets and lengths required for direct I/O, plot is always written in
+/// whole pieces, which are a multiple of this
+const ALIGNMENT: usize = 4096;
+
+/// Heap buffer aligned to [`ALIGNMENT`], suitable for writing with direct I/O
+pub(super) struct AlignedBuffer {
+    ptr: *mut u8,
+    len: usize,
+    layout: Layout,
+}
+
+// SAFETY: Buffer exclusively owns its allocation just like `Vec<u8>`
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    /// Allocate zeroed buffer of `len` bytes
+    pub(super) fn zeroed(len: usize) -> Self {
+        // Zero-sized allocations are not allowed
+        let layout = Layout::from_size_align(len.max(ALIGNMENT), ALIGNMENT).unwrap();
+        // SAFETY: Layout has non-zero size
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+
+        Self { ptr, len, layout }
+    }
+}
+
+impl Deref for AlignedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: Allocation is at least `len` bytes and initialized
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: Allocation is at least `len` bytes and initialized
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        // SAFETY: Allocated in constructor with the same layout
+        unsafe { alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+/// Open file for writing bypassing page cache, returns `None` if file system doesn't support it
+pub(super) fn open_direct(path: &Path) -> io::Result<Option<File>> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.custom_flags(libc::O_DIRECT);
+    }
+
+    let file = match options.open(path) {
+        Ok(file) => file,
+        Err(error) if error.raw_os_error() == Some(libc::EINVAL) => {
+            warn!("File system doesn't support direct I/O, falling back to buffered writes");
+            return Ok(None);
+        }
+        Err(error) => {
+            return Err(error);
+        }
+    };
+
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: File descriptor is valid for the lifetime of `file`
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(Some(file))
+}
+
+/// Reserve disk space for file to be `len` bytes long without changing its size, such that plot
+/// size still reflects number of pieces written, does nothing on platforms other than Linux
+pub(super) fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: File descriptor is valid for the lifetime of `file`
+        let result = unsafe {
+            libc::fallocate(
+                file.as_raw_fd(),
+                libc::FALLOC_FL_KEEP_SIZE,
+                0,
+                len as libc::off_t,
+            )
+        };
+        if result == -1 {
+            let error = io::Error::last_os_error();
+            // Not supported by file system or plot is not a regular file
+            if matches!(
+                error.raw_os_error(),
+                Some(libc::EOPNOTSUPP) | Some(libc::ENODEV)
+            ) {
+                warn!("Plot file doesn't support preallocation, skipping");
+            } else {
+                return Err(error);
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (file, len);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use crate::plot::PlotError;
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process;
+
+/// Name of the lock file in data directory
+pub(crate) const LOCK_FILE_NAME: &str = "farmer.lock";
+
+/// Advisory exclusive lock of the data directory, PID of the process holding the lock is stored in
+/// lock file. Lock is released on drop.
+#[derive(Debug)]
+pub(crate) struct DirectoryLock {
+    file: File,
+}
+
+impl Drop for DirectoryLock {
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
+
+impl DirectoryLock {
+    /// Acquire exclusive lock of data directory, fails with [`PlotError::Locked`] if lock is
+    /// held by another process (or by another lock instance in this process)
+    pub(crate) fn acquire(path: &Path) -> Result<Self, PlotError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path.join(LOCK_FILE_NAME))
+            .map_err(PlotError::PlotOpen)?;
+
+        if let Err(error) = file.try_lock_exclusive() {
+            if error.kind() != fs2::lock_contended_error().kind() {
+                return Err(PlotError::PlotOpen(error));
+            }
+
+            let mut contents = String::new();
+            let pid = file
+                .read_to_string(&mut contents)
+                .ok()
+                .and_then(|_| contents.trim().parse().ok());
+
+            return Err(PlotError::Locked { pid });
+        }
+
+        file.set_len(0).map_err(PlotError::PlotOpen)?;
+        write!(file, "{}", process::id()).map_err(PlotError::PlotOpen)?;
+
+        Ok(Self { file })
+    }
+}
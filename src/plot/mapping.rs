@@ -0,0 +1,252 @@
+use crate::PIECE_SIZE;
+use log::{debug, warn};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Once, RwLock};
+use std::{mem, ptr};
+
+/// Maximum number of plot mappings SIGBUS handler knows about, faults in mappings beyond that
+/// terminate process the same way as faults in any other mapping
+const MAX_REGISTERED_MAPPINGS: usize = 64;
+
+/// Address range of plot mapping, atomics are used since it is accessed from signal handler
+struct Slot {
+    /// Zero means slot is free
+    start: AtomicUsize,
+    len: AtomicUsize,
+    /// Set by signal handler once access to the mapping has faulted
+    poisoned: AtomicBool,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE_SLOT: Slot = Slot {
+    start: AtomicUsize::new(0),
+    len: AtomicUsize::new(0),
+    poisoned: AtomicBool::new(false),
+};
+
+static REGISTERED_MAPPINGS: [Slot; MAX_REGISTERED_MAPPINGS] = [FREE_SLOT; MAX_REGISTERED_MAPPINGS];
+
+/// Page size, faulting page is replaced with page of zeroes by signal handler
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+/// Memory mapping registered with SIGBUS handler for its lifetime
+struct RegisteredMmap {
+    mmap: Mmap,
+    slot: Option<usize>,
+}
+
+impl RegisteredMmap {
+    fn new(mmap: Mmap) -> Self {
+        let start = mmap.as_ptr() as usize;
+        let slot = REGISTERED_MAPPINGS.iter().position(|slot| {
+            if slot
+                .start
+                .compare_exchange(0, start, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                slot.poisoned.store(false, Ordering::Release);
+                slot.len.store(mmap.len(), Ordering::Release);
+                true
+            } else {
+                false
+            }
+        });
+
+        Self { mmap, slot }
+    }
+
+    /// Whether access to the mapping has faulted, in which case faulting pages were replaced with
+    /// zeroes and data read from the mapping can't be trusted
+    fn is_poisoned(&self) -> bool {
+        self.slot
+            .map(|slot| REGISTERED_MAPPINGS[slot].poisoned.load(Ordering::Acquire))
+            .unwrap_or_default()
+    }
+}
+
+impl Deref for RegisteredMmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.mmap
+    }
+}
+
+impl Drop for RegisteredMmap {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            let slot = &REGISTERED_MAPPINGS[slot];
+            slot.len.store(0, Ordering::Release);
+            slot.start.store(0, Ordering::Release);
+        }
+    }
+}
+
+/// Read-only memory mapping of the plot file, re-created when plot grows beyond mapped region.
+///
+/// Must only be used for plot that is locked for exclusive use: truncation of mapped file results
+/// in SIGBUS on access, same as I/O error. Once that happens mapping is abandoned and all reads go
+/// to the file directly, such that errors are returned from there.
+pub(super) struct Mapping {
+    file: Arc<File>,
+    /// `None` until there is something to map, empty files can't be mapped
+    mmap: RwLock<Option<RegisteredMmap>>,
+    /// Access to the mapping has faulted, file is read directly instead
+    poisoned: AtomicBool,
+}
+
+impl Mapping {
+    pub(super) fn new(file: Arc<File>) -> Self {
+        install_sigbus_handler();
+
+        Self {
+            file,
+            mmap: RwLock::default(),
+            poisoned: AtomicBool::new(false),
+        }
+    }
+
+    /// Copy `count` encodings starting at `first_index` from the mapping, file is re-mapped if it
+    /// has grown since it was mapped last time
+    pub(super) fn read(&self, first_index: u64, count: u64) -> io::Result<Vec<u8>> {
+        let start = (first_index * PIECE_SIZE as u64) as usize;
+        let end = start + (count * PIECE_SIZE as u64) as usize;
+
+        if !self.poisoned.load(Ordering::Acquire) {
+            if let Some(encodings) = self.read_mapped(start, end)? {
+                return Ok(encodings);
+            }
+            if !self.poisoned.swap(true, Ordering::AcqRel) {
+                warn!(
+                    "I/O error while reading memory-mapped plot file, reading it directly instead"
+                );
+            }
+        }
+
+        let mut encodings = vec![0u8; end - start];
+        self.file.read_exact_at(&mut encodings, start as u64)?;
+        Ok(encodings)
+    }
+
+    /// Copy `start..end` bytes from the mapping, returns `None` if mapping was poisoned
+    fn read_mapped(&self, start: usize, end: usize) -> io::Result<Option<Vec<u8>>> {
+        let copy = |mmap: &RegisteredMmap| {
+            let encodings = mmap[start..end].to_vec();
+            if mmap.is_poisoned() {
+                None
+            } else {
+                Some(encodings)
+            }
+        };
+
+        {
+            let mmap = self.mmap.read().unwrap();
+            if let Some(mmap) = mmap.as_ref() {
+                if mmap.len() >= end {
+                    return Ok(copy(mmap));
+                }
+            }
+        }
+
+        let mut mmap = self.mmap.write().unwrap();
+        let mapped_len = mmap.as_ref().map(|mmap| mmap.len()).unwrap_or_default();
+        if mapped_len < end {
+            if (self.file.metadata()?.len() as usize) < end {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ));
+            }
+            debug!("Re-mapping plot file, {} bytes were mapped", mapped_len);
+            // SAFETY: Mapped region of the file is only ever appended to, never truncated. Only
+            // plots opened for writing are mapped and those are locked for exclusive use while
+            // open, but lock is advisory, so external modifications and I/O errors are still
+            // possible and result in SIGBUS, which is handled by `handle_sigbus`.
+            mmap.replace(RegisteredMmap::new(unsafe { Mmap::map(&*self.file)? }));
+        }
+
+        Ok(copy(mmap.as_ref().unwrap()))
+    }
+}
+
+/// Reading from a mapping of a file that was truncated or can't be read due to I/O error results in
+/// SIGBUS, which would otherwise terminate the process.
+///
+/// Handler is process-wide, SIGBUS outside of plot mappings is handled the same way as without it.
+fn install_sigbus_handler() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| unsafe {
+        PAGE_SIZE.store(
+            libc::sysconf(libc::_SC_PAGESIZE) as usize,
+            Ordering::Release,
+        );
+
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_sigbus as usize;
+        action.sa_flags = libc::SA_SIGINFO;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGBUS, &action, ptr::null_mut());
+    });
+}
+
+extern "C" fn handle_sigbus(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _context: *mut libc::c_void,
+) {
+    // Only async-signal-safe functions can be used here
+    const MESSAGE: &[u8] = b"I/O error while reading memory-mapped plot file (SIGBUS) that can't \
+        be recovered from, plot file was truncated or disk is failing, try again without mmap\n";
+
+    unsafe {
+        let address = (*info).si_addr() as usize;
+        let slot = REGISTERED_MAPPINGS.iter().find(|slot| {
+            let start = slot.start.load(Ordering::Acquire);
+            start != 0 && (start..start + slot.len.load(Ordering::Acquire)).contains(&address)
+        });
+        let slot = match slot {
+            Some(slot) => slot,
+            None => {
+                // Not ours, restore default disposition, signal is delivered again once handler
+                // returns and terminates the process as usual
+                let mut action: libc::sigaction = mem::zeroed();
+                action.sa_sigaction = libc::SIG_DFL;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, ptr::null_mut());
+                libc::raise(signal);
+                return;
+            }
+        };
+
+        // Replace faulting page with page of zeroes, such that access is retried successfully
+        // once handler returns, and mark mapping as poisoned, so that whatever was read from it is
+        // discarded by `Mapping::read`
+        let page_size = PAGE_SIZE.load(Ordering::Acquire);
+        let page = libc::mmap(
+            (address - address % page_size) as *mut libc::c_void,
+            page_size,
+            libc::PROT_READ,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+            -1,
+            0,
+        );
+        if page != libc::MAP_FAILED {
+            slot.poisoned.store(true, Ordering::Release);
+            return;
+        }
+
+        libc::write(
+            libc::STDERR_FILENO,
+            MESSAGE.as_ptr() as *const libc::c_void,
+            MESSAGE.len(),
+        );
+        // EX_IOERR
+        libc::_exit(74);
+    }
+}
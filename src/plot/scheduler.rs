@@ -0,0 +1,389 @@
+use futures::channel::mpsc;
+use futures::future;
+use futures::lock::Mutex as AsyncMutex;
+use futures::{FutureExt, StreamExt};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Number of requests of higher priority that can be dispatched while request of lower priority is
+/// waiting, after which the lower priority request is dispatched regardless
+const MAX_BYPASSES: u32 = 16;
+
+/// Priority classes of plot requests, from highest to lowest
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RequestPriority {
+    /// Reading single encoding and searching commitment for solution
+    SolutionLookup,
+    /// Reading encodings in bulk to create commitment
+    CommitmentRead,
+    /// Writing encodings and tags
+    Write,
+    /// Finishing and removing commitments
+    Maintenance,
+}
+
+impl RequestPriority {
+    const ALL: [RequestPriority; 4] = [
+        RequestPriority::SolutionLookup,
+        RequestPriority::CommitmentRead,
+        RequestPriority::Write,
+        RequestPriority::Maintenance,
+    ];
+}
+
+impl fmt::Display for RequestPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RequestPriority::SolutionLookup => "solution_lookup",
+            RequestPriority::CommitmentRead => "commitment_read",
+            RequestPriority::Write => "write",
+            RequestPriority::Maintenance => "maintenance",
+        })
+    }
+}
+
+/// Time requests of particular priority spent waiting in plot's queue before being dispatched
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize)]
+pub struct QueueWaitStats {
+    /// Number of dispatched requests
+    pub requests: u64,
+    /// Total time dispatched requests spent waiting
+    pub total: Duration,
+    /// Longest time a request spent waiting
+    pub max: Duration,
+}
+
+struct Queued<T> {
+    request: T,
+    enqueued: Instant,
+}
+
+/// Scheduler was dropped and doesn't accept requests anymore
+#[derive(Debug)]
+pub(super) struct SchedulerClosed;
+
+/// Sending side of the scheduler, cheap to clone
+pub(super) struct SchedulerSender<T> {
+    senders: Arc<Vec<mpsc::UnboundedSender<Queued<T>>>>,
+    /// Permits to enqueue request of each priority, one is taken for every request that is sent
+    /// and returned once request is dispatched, such that there are never more than `queue_size`
+    /// requests queued
+    permits: Arc<Vec<AsyncMutex<mpsc::UnboundedReceiver<()>>>>,
+    stats: Arc<Mutex<HashMap<RequestPriority, QueueWaitStats>>>,
+}
+
+impl<T> Clone for SchedulerSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            senders: Arc::clone(&self.senders),
+            permits: Arc::clone(&self.permits),
+            stats: Arc::clone(&self.stats),
+        }
+    }
+}
+
+impl<T> SchedulerSender<T> {
+    /// Enqueue request with specified priority, waits if queue is full, returns error if
+    /// scheduler is dropped.
+    ///
+    /// Request is enqueued if and only if returned future completes, such that dropping it while
+    /// it waits for space in the queue cancels request.
+    pub(super) async fn send(
+        &self,
+        priority: RequestPriority,
+        request: T,
+    ) -> Result<(), SchedulerClosed> {
+        self.permits[priority as usize]
+            .lock()
+            .await
+            .next()
+            .await
+            .ok_or(SchedulerClosed)?;
+
+        self.senders[priority as usize]
+            .unbounded_send(Queued {
+                request,
+                enqueued: Instant::now(),
+            })
+            .map_err(|_| SchedulerClosed)
+    }
+
+    pub(super) fn wait_stats(&self) -> HashMap<RequestPriority, QueueWaitStats> {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+/// Dispatches requests from per-priority queues strictly by priority, except that request that was
+/// bypassed by [`MAX_BYPASSES`] requests of higher priority is dispatched next, so that lower
+/// priority requests are never delayed indefinitely
+pub(super) struct Scheduler<T> {
+    /// Receiver of each queue, `None` once queue is closed and drained
+    receivers: Vec<Option<mpsc::UnboundedReceiver<Queued<T>>>>,
+    /// Request at the head of each queue
+    pending: Vec<Option<Queued<T>>>,
+    /// Number of times pending request of each priority was bypassed
+    bypassed: Vec<u32>,
+    /// Permits are returned through these once requests are dispatched
+    permits: Vec<mpsc::UnboundedSender<()>>,
+    stats: Arc<Mutex<HashMap<RequestPriority, QueueWaitStats>>>,
+}
+
+/// Create scheduler with queues of specified capacity (at least 1) for each priority
+pub(super) fn channel<T>(queue_size: usize) -> (SchedulerSender<T>, Scheduler<T>) {
+    assert!(queue_size > 0, "Queue size must be at least 1");
+
+    let (senders, receivers): (Vec<_>, _) = RequestPriority::ALL
+        .iter()
+        .map(|_| {
+            let (sender, receiver) = mpsc::unbounded();
+            (sender, Some(receiver))
+        })
+        .unzip();
+    let (permit_senders, permit_receivers): (Vec<_>, Vec<_>) = RequestPriority::ALL
+        .iter()
+        .map(|_| {
+            let (permit_sender, permit_receiver) = mpsc::unbounded();
+            for _ in 0..queue_size {
+                permit_sender.unbounded_send(()).unwrap();
+            }
+            (permit_sender, AsyncMutex::new(permit_receiver))
+        })
+        .unzip();
+    let stats = Arc::<Mutex<HashMap<_, _>>>::default();
+
+    (
+        SchedulerSender {
+            senders: Arc::new(senders),
+            permits: Arc::new(permit_receivers),
+            stats: Arc::clone(&stats),
+        },
+        Scheduler {
+            receivers,
+            pending: RequestPriority::ALL.iter().map(|_| None).collect(),
+            bypassed: vec![0; RequestPriority::ALL.len()],
+            permits: permit_senders,
+            stats,
+        },
+    )
+}
+
+impl<T> Scheduler<T> {
+    /// Wait for the next request to dispatch, returns `None` once senders are dropped and all
+    /// queued requests are dispatched
+    pub(super) async fn next(&mut self) -> Option<T> {
+        loop {
+            for (pending, receiver) in self.pending.iter_mut().zip(&mut self.receivers) {
+                if pending.is_some() {
+                    continue;
+                }
+                if let Some(queue) = receiver {
+                    match queue.try_next() {
+                        Ok(Some(request)) => {
+                            pending.replace(request);
+                        }
+                        Ok(None) => {
+                            // Closed and drained
+                            receiver.take();
+                        }
+                        Err(_) => {
+                            // Empty
+                        }
+                    }
+                }
+            }
+
+            if let Some(index) = self.pick() {
+                return Some(self.dispatch(index));
+            }
+
+            if self.receivers.iter().all(Option::is_none) {
+                return None;
+            }
+
+            // Nothing to dispatch, wait for any request to come in
+            let ((index, request), _, _) =
+                future::select_all(self.receivers.iter_mut().enumerate().filter_map(
+                    |(index, receiver)| {
+                        let receiver = receiver.as_mut()?;
+                        Some(receiver.next().map(move |request| (index, request)))
+                    },
+                ))
+                .await;
+            match request {
+                Some(request) => {
+                    self.pending[index].replace(request);
+                }
+                None => {
+                    self.receivers[index].take();
+                }
+            }
+        }
+    }
+
+    fn pick(&self) -> Option<usize> {
+        let mut pending = self
+            .pending
+            .iter()
+            .enumerate()
+            .filter(|(_index, pending)| pending.is_some())
+            .map(|(index, _pending)| index);
+
+        pending
+            .clone()
+            .find(|&index| self.bypassed[index] >= MAX_BYPASSES)
+            .or_else(|| pending.next())
+    }
+
+    fn dispatch(&mut self, index: usize) -> T {
+        let Queued { request, enqueued } = self.pending[index].take().unwrap();
+        // Senders are gone if permit can't be returned, nothing to do then
+        let _ = self.permits[index].unbounded_send(());
+        self.bypassed[index] = 0;
+        for (pending, bypassed) in self.pending.iter().zip(&mut self.bypassed).skip(index + 1) {
+            if pending.is_some() {
+                *bypassed += 1;
+            }
+        }
+
+        let wait = enqueued.elapsed();
+        let mut stats = self.stats.lock().unwrap();
+        let stats = stats.entry(RequestPriority::ALL[index]).or_default();
+        stats.requests += 1;
+        stats.total += wait;
+        stats.max = stats.max.max(wait);
+
+        request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_scheduler() {
+        let (sender, mut scheduler) = channel(100);
+
+        sender.send(RequestPriority::Maintenance, 0).await.unwrap();
+        sender.send(RequestPriority::Write, 1).await.unwrap();
+        sender
+            .send(RequestPriority::SolutionLookup, 2)
+            .await
+            .unwrap();
+        assert_eq!(scheduler.next().await, Some(2));
+        assert_eq!(scheduler.next().await, Some(1));
+        assert_eq!(scheduler.next().await, Some(0));
+
+        // Write is dispatched despite steady stream of reads
+        sender.send(RequestPriority::Write, 1).await.unwrap();
+        for _ in 0..MAX_BYPASSES * 2 {
+            sender
+                .send(RequestPriority::CommitmentRead, 2)
+                .await
+                .unwrap();
+        }
+        let dispatched = {
+            let mut dispatched = Vec::new();
+            for _ in 0..MAX_BYPASSES * 2 + 1 {
+                dispatched.push(scheduler.next().await.unwrap());
+            }
+            dispatched
+        };
+        assert_eq!(
+            dispatched.iter().position(|&request| request == 1),
+            Some(MAX_BYPASSES as usize)
+        );
+
+        let stats = sender.wait_stats();
+        assert_eq!(stats[&RequestPriority::Write].requests, 2);
+        assert_eq!(stats[&RequestPriority::CommitmentRead].requests, 32);
+
+        drop(sender);
+        assert_eq!(scheduler.next().await, None);
+    }
+
+    #[async_std::test]
+    async fn test_scheduler_drains_queues_on_close() {
+        let (sender, mut scheduler) = channel(100);
+
+        sender.send(RequestPriority::Write, 1).await.unwrap();
+        sender.send(RequestPriority::Maintenance, 2).await.unwrap();
+        drop(sender);
+
+        // Requests queued before senders were dropped are still dispatched
+        assert_eq!(scheduler.next().await, Some(1));
+        assert_eq!(scheduler.next().await, Some(2));
+        assert_eq!(scheduler.next().await, None);
+        assert_eq!(scheduler.next().await, None);
+    }
+
+    #[async_std::test]
+    async fn test_scheduler_queue_size() {
+        for &queue_size in &[1, 7] {
+            let (sender, _scheduler) = channel(queue_size);
+
+            let mut queued = 0;
+            while sender
+                .send(RequestPriority::Write, queued)
+                .now_or_never()
+                .is_some()
+            {
+                queued += 1;
+            }
+            assert_eq!(queued, queue_size);
+        }
+    }
+
+    #[async_std::test]
+    async fn test_scheduler_backpressure() {
+        let queue_size = 4;
+        let (sender, mut scheduler) = channel(queue_size);
+
+        // Sends wait once there are `queue_size` requests in the queue, regardless of how many
+        // clones of the sender there are
+        let mut queued = 0;
+        while sender
+            .send(RequestPriority::Write, queued)
+            .now_or_never()
+            .is_some()
+        {
+            queued += 1;
+        }
+        assert_eq!(queued, queue_size);
+        assert!(sender
+            .clone()
+            .send(RequestPriority::Write, 100)
+            .now_or_never()
+            .is_none());
+
+        // Other priorities have their own queues
+        sender
+            .send(RequestPriority::SolutionLookup, 200)
+            .await
+            .unwrap();
+        assert_eq!(scheduler.next().await, Some(200));
+
+        // Space is freed as requests are dispatched
+        assert_eq!(scheduler.next().await, Some(0));
+        sender
+            .send(RequestPriority::Write, queued)
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        assert!(sender
+            .send(RequestPriority::Write, 100)
+            .now_or_never()
+            .is_none());
+
+        // Requests whose sends were dropped before completion were not enqueued
+        drop(sender);
+        for expected in 1..=queued {
+            assert_eq!(scheduler.next().await, Some(expected));
+        }
+        assert_eq!(scheduler.next().await, None);
+    }
+}
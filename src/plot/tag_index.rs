@@ -0,0 +1,223 @@
+use crate::{utils, Salt};
+use log::{debug, warn};
+use rocksdb::{DBWithThreadMode, SingleThreaded};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Desired number of tags in each bucket of the index
+const TAGS_PER_BUCKET: u64 = 16;
+/// Upper bound for number of buckets (`2^MAX_PREFIX_BITS`), limits memory usage of the index to
+/// 16 MiB per commitment
+const MAX_PREFIX_BITS: u32 = 20;
+/// How often index build checks whether it is still needed
+const CHECK_INTERVAL: u64 = 65536;
+
+/// Sparse in-memory index of commitment's tags: tags are split into buckets by their high bits and
+/// smallest and largest tag of each bucket is stored, which allows to tell where the first tag
+/// within a range is in the database or that there is no such tag without touching the database
+#[derive(Debug)]
+pub(super) struct TagIndex {
+    /// Number of high bits of a tag that determine its bucket
+    prefix_bits: u32,
+    /// Smallest and largest tag in each bucket, empty buckets have smallest tag larger than
+    /// largest
+    buckets: Vec<(u64, u64)>,
+}
+
+impl TagIndex {
+    /// Create empty index sized for specified number of tags
+    fn with_capacity(tag_count: u64) -> Self {
+        let prefix_bits = (tag_count / TAGS_PER_BUCKET)
+            .next_power_of_two()
+            .trailing_zeros()
+            .min(MAX_PREFIX_BITS);
+
+        Self {
+            prefix_bits,
+            buckets: vec![(u64::MAX, 0); 1 << prefix_bits],
+        }
+    }
+
+    fn bucket(&self, tag: u64) -> usize {
+        tag.checked_shr(64 - self.prefix_bits).unwrap_or_default() as usize
+    }
+
+    fn insert(&mut self, tag: u64) {
+        let bucket = self.bucket(tag);
+        let (min, max) = &mut self.buckets[bucket];
+        *min = tag.min(*min);
+        *max = tag.max(*max);
+    }
+
+    /// Key to seek the database to in order to find the first tag within `lower..=upper` range,
+    /// `None` if there are definitely no tags in this range
+    pub(super) fn seek_hint(&self, lower: u64, upper: u64) -> Option<u64> {
+        if lower > upper {
+            return None;
+        }
+
+        // Buckets are ordered, so the first bucket with tags not below `lower` contains the first
+        // tag that might be in range
+        let (min, _max) = self.buckets[self.bucket(lower)..=self.bucket(upper)]
+            .iter()
+            .find(|(min, max)| min <= max && *max >= lower)?;
+
+        if *min > upper {
+            None
+        } else {
+            Some(lower.max(*min))
+        }
+    }
+}
+
+enum TagIndexState {
+    /// Index is being built, identified by build ID
+    Building(u64),
+    Built(Arc<TagIndex>),
+}
+
+/// Tag indexes of finished commitments, indexes are built in background and are discarded as soon
+/// as commitment is modified or removed, cheap to clone
+#[derive(Clone, Default)]
+pub(super) struct TagIndexes {
+    states: Arc<Mutex<HashMap<Salt, TagIndexState>>>,
+    next_build_id: Arc<AtomicU64>,
+}
+
+impl TagIndexes {
+    /// Index for specified salt if it was built already
+    pub(super) fn get(&self, salt: &Salt) -> Option<Arc<TagIndex>> {
+        match self.states.lock().unwrap().get(salt) {
+            Some(TagIndexState::Built(tag_index)) => Some(Arc::clone(tag_index)),
+            Some(TagIndexState::Building(_)) | None => None,
+        }
+    }
+
+    /// Build index of `tag_count` tags from commitment database in background, replaces existing
+    /// index for the same salt once built
+    pub(super) fn build(
+        &self,
+        salt: Salt,
+        tags_db: Arc<DBWithThreadMode<SingleThreaded>>,
+        tag_count: u64,
+    ) {
+        let build_id = self.next_build_id.fetch_add(1, Ordering::AcqRel);
+        self.states
+            .lock()
+            .unwrap()
+            .insert(salt, TagIndexState::Building(build_id));
+
+        let tag_indexes = self.clone();
+        utils::spawn_blocking(move || {
+            let is_current = || {
+                matches!(
+                    tag_indexes.states.lock().unwrap().get(&salt),
+                    Some(TagIndexState::Building(id)) if *id == build_id
+                )
+            };
+
+            let started = Instant::now();
+            let mut tag_index = TagIndex::with_capacity(tag_count);
+            let mut iter = tags_db.raw_iterator();
+            iter.seek_to_first();
+            let mut indexed = 0u64;
+            while let Some(tag) = iter.key() {
+                match tag.try_into() {
+                    Ok(tag) => {
+                        tag_index.insert(u64::from_be_bytes(tag));
+                    }
+                    Err(_) => {
+                        warn!(
+                            "Invalid tag in commitment {}, not indexing it",
+                            hex::encode(salt)
+                        );
+                        tag_indexes.remove(&salt);
+                        return;
+                    }
+                }
+                indexed += 1;
+                if indexed % CHECK_INTERVAL == 0 && !is_current() {
+                    return;
+                }
+                iter.next();
+            }
+            if let Err(error) = iter.status() {
+                warn!(
+                    "Failed to index commitment {}: {}",
+                    hex::encode(salt),
+                    error
+                );
+                tag_indexes.remove(&salt);
+                return;
+            }
+
+            let mut states = tag_indexes.states.lock().unwrap();
+            if matches!(states.get(&salt), Some(TagIndexState::Building(id)) if *id == build_id) {
+                debug!(
+                    "Indexed {} tags of commitment {} in {:?}",
+                    indexed,
+                    hex::encode(salt),
+                    started.elapsed()
+                );
+                states.insert(salt, TagIndexState::Built(Arc::new(tag_index)));
+            }
+        });
+    }
+
+    /// Discard index for specified salt, index that is being built is discarded once build
+    /// finishes
+    pub(super) fn remove(&self, salt: &Salt) {
+        self.states.lock().unwrap().remove(salt);
+    }
+
+    /// Discard all indexes and stop builds in progress
+    pub(super) fn clear(&self) {
+        self.states.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::prelude::*;
+
+    #[test]
+    fn test_seek_hint() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut tags: Vec<u64> = (0..1000).map(|_| rng.gen()).collect();
+        tags.sort_unstable();
+
+        let mut tag_index = TagIndex::with_capacity(tags.len() as u64);
+        for &tag in &tags {
+            tag_index.insert(tag);
+        }
+
+        for _ in 0..1000 {
+            let lower: u64 = rng.gen();
+            let upper = lower.saturating_add(rng.gen_range(0..u64::MAX / 100));
+            let first_tag = tags
+                .iter()
+                .copied()
+                .find(|tag| (lower..=upper).contains(tag));
+
+            match tag_index.seek_hint(lower, upper) {
+                Some(hint) => {
+                    // Seeking to hint must not skip the first tag within range
+                    assert!(hint >= lower && hint <= upper);
+                    if let Some(first_tag) = first_tag {
+                        assert!(hint <= first_tag);
+                    }
+                }
+                None => {
+                    assert_eq!(first_tag, None);
+                }
+            }
+        }
+
+        // Empty index has no tags in any range
+        assert_eq!(TagIndex::with_capacity(0).seek_hint(0, u64::MAX), None);
+    }
+}
@@ -0,0 +1,47 @@
+//! Types exchanged with the node over JSON-RPC
+use crate::{Salt, Tag, PRIME_SIZE_BYTES};
+use serde::{Deserialize, Serialize};
+
+/// Slot number
+pub type SlotNumber = u64;
+
+/// Solution for a slot challenge found in the plot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Solution {
+    /// Farmer's public key
+    pub public_key: [u8; 32],
+    /// Index of the encoding in the plot
+    pub nonce: u64,
+    /// Encoding
+    pub encoding: Vec<u8>,
+    /// Signature of the tag
+    pub signature: Vec<u8>,
+    /// Tag of the encoding for current salt
+    pub tag: Tag,
+}
+
+/// Proposed proof of space consisting of solution and farmer's secret key for block signing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProposedProofOfSpaceResponse {
+    /// Slot number
+    pub slot_number: SlotNumber,
+    /// Solution (if present) from farmer's plot corresponding to slot number above
+    pub solution: Option<Solution>,
+    /// Secret key, used for signing blocks on the client node
+    pub secret_key: Vec<u8>,
+}
+
+/// Information about new slot that just arrived
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlotInfo {
+    /// Slot number
+    pub slot_number: SlotNumber,
+    /// Slot challenge
+    pub challenge: [u8; PRIME_SIZE_BYTES],
+    /// Salt
+    pub salt: Salt,
+    /// Salt for the next eon
+    pub next_salt: Option<Salt>,
+    /// Acceptable solution range
+    pub solution_range: u64,
+}
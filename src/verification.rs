@@ -0,0 +1,216 @@
+//! Verification of solutions
+use crate::rpc::{SlotInfo, Solution};
+use crate::{crypto, Piece, Tag, ENCODE_ROUNDS, PIECE_SIZE, PRIME_SIZE_BYTES, SIGNING_CONTEXT};
+use schnorrkel::{PublicKey, Signature};
+use spartan_codec::Spartan;
+use std::convert::TryInto;
+use thiserror::Error;
+
+/// Reasons solution can be invalid
+#[derive(Debug, Error)]
+pub enum VerificationError {
+    /// Solution public key doesn't match farmer's public key
+    #[error("Solution public key doesn't match farmer's public key")]
+    PublicKeyMismatch,
+    /// Tag doesn't match encoding and salt
+    #[error("Tag doesn't match encoding and salt")]
+    TagMismatch,
+    /// Tag is outside of solution range
+    #[error("Tag is outside of solution range")]
+    OutOfRange,
+    /// Signature is invalid
+    #[error("Invalid signature: {0}")]
+    InvalidSignature(schnorrkel::SignatureError),
+    /// Encoding doesn't decode into genesis piece for nonce
+    #[error("Encoding is not valid for nonce {0}")]
+    InvalidEncoding(u64),
+}
+
+/// Checks whether `tag` is within `range` around `target`, accounting for wrapping around `u64`
+/// the same way as tags lookup in the plot does
+pub fn is_within_solution_range(tag: Tag, target: Tag, range: u64) -> bool {
+    let tag = u64::from_be_bytes(tag);
+    let target = u64::from_be_bytes(target);
+    let distance = tag.wrapping_sub(target).min(target.wrapping_sub(tag));
+
+    distance <= range / 2
+}
+
+/// Verifies solution found for slot by farmer with specified public key, returns an error
+/// describing first check that didn't pass
+pub fn verify_solution(
+    solution: &Solution,
+    slot_info: &SlotInfo,
+    public_key: &PublicKey,
+    spartan: &Spartan<PRIME_SIZE_BYTES, PIECE_SIZE>,
+) -> Result<(), VerificationError> {
+    if solution.public_key != public_key.to_bytes() {
+        return Err(VerificationError::PublicKeyMismatch);
+    }
+
+    if solution.tag != crypto::create_tag(&solution.encoding, &slot_info.salt) {
+        return Err(VerificationError::TagMismatch);
+    }
+
+    let public_key_hash = crypto::hash_public_key(public_key);
+    let local_challenge = crypto::derive_local_challenge(&slot_info.challenge, &public_key_hash);
+    if !is_within_solution_range(solution.tag, local_challenge, slot_info.solution_range) {
+        return Err(VerificationError::OutOfRange);
+    }
+
+    let signature =
+        Signature::from_bytes(&solution.signature).map_err(VerificationError::InvalidSignature)?;
+    let ctx = schnorrkel::context::signing_context(SIGNING_CONTEXT);
+    public_key
+        .verify(ctx.bytes(&solution.tag), &signature)
+        .map_err(VerificationError::InvalidSignature)?;
+
+    let encoding: Piece = solution
+        .encoding
+        .as_slice()
+        .try_into()
+        .map_err(|_| VerificationError::InvalidEncoding(solution.nonce))?;
+    if !spartan.is_valid(encoding, public_key_hash, solution.nonce, ENCODE_ROUNDS) {
+        return Err(VerificationError::InvalidEncoding(solution.nonce));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Salt;
+    use schnorrkel::Keypair;
+
+    fn create_solution(
+        keypair: &Keypair,
+        spartan: &Spartan<PRIME_SIZE_BYTES, PIECE_SIZE>,
+        nonce: u64,
+        salt: Salt,
+    ) -> Solution {
+        let ctx = schnorrkel::context::signing_context(SIGNING_CONTEXT);
+        let encoding = spartan.encode(
+            crypto::hash_public_key(&keypair.public),
+            nonce,
+            ENCODE_ROUNDS,
+        );
+        let tag = crypto::create_tag(&encoding, &salt);
+
+        Solution {
+            public_key: keypair.public.to_bytes(),
+            nonce,
+            encoding: encoding.to_vec(),
+            signature: keypair.sign(ctx.bytes(&tag)).to_bytes().to_vec(),
+            tag,
+        }
+    }
+
+    #[test]
+    fn test_is_within_solution_range() {
+        let range = 10;
+        assert!(is_within_solution_range(
+            100_u64.to_be_bytes(),
+            100_u64.to_be_bytes(),
+            range
+        ));
+        assert!(is_within_solution_range(
+            105_u64.to_be_bytes(),
+            100_u64.to_be_bytes(),
+            range
+        ));
+        assert!(is_within_solution_range(
+            95_u64.to_be_bytes(),
+            100_u64.to_be_bytes(),
+            range
+        ));
+        assert!(!is_within_solution_range(
+            106_u64.to_be_bytes(),
+            100_u64.to_be_bytes(),
+            range
+        ));
+        assert!(!is_within_solution_range(
+            94_u64.to_be_bytes(),
+            100_u64.to_be_bytes(),
+            range
+        ));
+        // Wraps around
+        assert!(is_within_solution_range(
+            u64::MAX.to_be_bytes(),
+            2_u64.to_be_bytes(),
+            range
+        ));
+        assert!(is_within_solution_range(
+            2_u64.to_be_bytes(),
+            u64::MAX.to_be_bytes(),
+            range
+        ));
+    }
+
+    #[test]
+    fn test_verify_solution() {
+        let keypair = Keypair::generate();
+        let spartan = Spartan::<PRIME_SIZE_BYTES, PIECE_SIZE>::new(
+            crypto::genesis_piece_from_seed("spartan"),
+        );
+        let salt: Salt = [1u8; 8];
+        let mut slot_info = SlotInfo {
+            slot_number: 1,
+            challenge: [2u8; 8],
+            salt,
+            next_salt: None,
+            solution_range: u64::MAX,
+        };
+
+        let solution = create_solution(&keypair, &spartan, 3, salt);
+        assert!(verify_solution(&solution, &slot_info, &keypair.public, &spartan).is_ok());
+
+        let other_keypair = Keypair::generate();
+        assert!(matches!(
+            verify_solution(&solution, &slot_info, &other_keypair.public, &spartan),
+            Err(VerificationError::PublicKeyMismatch)
+        ));
+
+        {
+            let mut solution = create_solution(&keypair, &spartan, 3, salt);
+            solution.tag[0] ^= 1;
+            assert!(matches!(
+                verify_solution(&solution, &slot_info, &keypair.public, &spartan),
+                Err(VerificationError::TagMismatch)
+            ));
+        }
+
+        {
+            let mut solution = create_solution(&keypair, &spartan, 3, salt);
+            solution.signature = other_keypair
+                .sign(schnorrkel::context::signing_context(SIGNING_CONTEXT).bytes(&solution.tag))
+                .to_bytes()
+                .to_vec();
+            assert!(matches!(
+                verify_solution(&solution, &slot_info, &keypair.public, &spartan),
+                Err(VerificationError::InvalidSignature(_))
+            ));
+        }
+
+        {
+            let mut solution = create_solution(&keypair, &spartan, 3, salt);
+            solution.nonce = 4;
+            assert!(matches!(
+                verify_solution(&solution, &slot_info, &keypair.public, &spartan),
+                Err(VerificationError::InvalidEncoding(4))
+            ));
+        }
+
+        let local_challenge = crypto::derive_local_challenge(
+            &slot_info.challenge,
+            &crypto::hash_public_key(&keypair.public),
+        );
+        // Only exact match is within zero solution range
+        assert_ne!(solution.tag, local_challenge);
+        slot_info.solution_range = 0;
+        assert!(matches!(
+            verify_solution(&solution, &slot_info, &keypair.public, &spartan),
+            Err(VerificationError::OutOfRange)
+        ));
+    }
+}
@@ -0,0 +1,297 @@
+//! Test harness with mock node that speaks the same WebSocket JSON-RPC protocol as spartan-client
+
+use async_std::future::timeout;
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task;
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, Either};
+use futures::{stream, AsyncReadExt, AsyncWriteExt, FutureExt, StreamExt};
+use serde_json::{json, Value};
+use soketto::handshake::{server::Response, Server};
+use spartan_farmer::rpc::{ProposedProofOfSpaceResponse, SlotInfo};
+use std::collections::VecDeque;
+use std::fs;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How long to wait for farmer to react to a slot before failing the test
+const PROPOSAL_TIMEOUT: Duration = Duration::from_secs(60);
+const SUBSCRIPTION_ID: u64 = 1;
+
+pub struct TargetDirectory {
+    path: PathBuf,
+}
+
+impl Drop for TargetDirectory {
+    fn drop(&mut self) {
+        drop(fs::remove_dir_all(&self.path));
+    }
+}
+
+impl Deref for TargetDirectory {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &Self::Target {
+        &self.path
+    }
+}
+
+impl TargetDirectory {
+    pub fn new(test_name: &str) -> Self {
+        let path = PathBuf::from("target").join(test_name);
+
+        drop(fs::remove_dir_all(&path));
+        fs::create_dir_all(&path).unwrap();
+
+        Self { path }
+    }
+}
+
+pub fn init() {
+    let _ = env_logger::builder().is_test(true).try_init();
+}
+
+/// Find local address that is not in use at the moment
+pub fn free_address() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Make HTTP request and return response status code and body
+pub async fn http_request(address: SocketAddr, method: &str, path: &str) -> (u16, String) {
+    http_request_with_headers(address, method, path, &[("Host", &address.to_string())]).await
+}
+
+/// Same as [`http_request`], but with custom headers
+pub async fn http_request_with_headers(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> (u16, String) {
+    let mut request = format!("{} {} HTTP/1.1\r\n", method, path);
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+
+    (status, body.to_string())
+}
+
+enum Event {
+    Request(Value),
+    Slot(SlotInfo),
+    Disconnect,
+    Stop,
+}
+
+/// Mock node that accepts farmer connections one at a time, sends scripted slots to it and
+/// collects proposed solutions
+pub struct MockNode {
+    address: String,
+    slot_sender: mpsc::UnboundedSender<SlotInfo>,
+    proposal_receiver: mpsc::UnboundedReceiver<ProposedProofOfSpaceResponse>,
+    disconnect_sender: mpsc::UnboundedSender<()>,
+    stop_sender: Option<oneshot::Sender<()>>,
+}
+
+impl Drop for MockNode {
+    fn drop(&mut self) {
+        if let Some(stop_sender) = self.stop_sender.take() {
+            let _ = stop_sender.send(());
+        }
+    }
+}
+
+impl MockNode {
+    /// Start mock node listening on a random local port
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("ws://{}", listener.local_addr().unwrap());
+        let (slot_sender, slot_receiver) = mpsc::unbounded();
+        let (proposal_sender, proposal_receiver) = mpsc::unbounded();
+        let (disconnect_sender, disconnect_receiver) = mpsc::unbounded();
+        let (stop_sender, stop_receiver) = oneshot::channel();
+
+        task::spawn(async move {
+            let mut events = stream::select(
+                stream::select(
+                    slot_receiver.map(Event::Slot),
+                    disconnect_receiver.map(|_| Event::Disconnect),
+                ),
+                stop_receiver.into_stream().map(|_| Event::Stop),
+            );
+            let mut pending_slots = VecDeque::new();
+
+            loop {
+                let socket = loop {
+                    match future::select(Box::pin(listener.accept()), events.next()).await {
+                        Either::Left((result, _)) => {
+                            break result.unwrap().0;
+                        }
+                        Either::Right((Some(Event::Slot(slot_info)), _)) => {
+                            pending_slots.push_back(slot_info);
+                        }
+                        Either::Right((Some(Event::Disconnect), _)) => {
+                            // Not connected anyway
+                        }
+                        Either::Right(_) => {
+                            return;
+                        }
+                    }
+                };
+                let mut server = Server::new(socket);
+                let key = server.receive_request().await.unwrap().into_key();
+                server
+                    .send_response(&Response::Accept {
+                        key: &key,
+                        protocol: None,
+                    })
+                    .await
+                    .unwrap();
+                let (mut sender, mut receiver) = server.into_builder().finish();
+
+                let (request_sender, request_receiver) = mpsc::unbounded();
+                task::spawn(async move {
+                    let mut message = Vec::new();
+                    while receiver.receive_data(&mut message).await.is_ok() {
+                        let request = serde_json::from_slice(&message).unwrap();
+                        message.clear();
+                        if request_sender.unbounded_send(request).is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                let mut connection_events =
+                    stream::select(request_receiver.map(Event::Request), events.by_ref());
+                let mut subscribed = false;
+
+                while let Some(event) = connection_events.next().await {
+                    let mut messages = Vec::new();
+                    match event {
+                        Event::Request(request) => {
+                            let id = request["id"].clone();
+                            let result = match request["method"].as_str() {
+                                Some("poc_subscribeSlotInfo") => {
+                                    subscribed = true;
+                                    json!(SUBSCRIPTION_ID)
+                                }
+                                Some("poc_unsubscribeSlotInfo") => {
+                                    subscribed = false;
+                                    json!(true)
+                                }
+                                Some("poc_proposeProofOfSpace") => {
+                                    let proposal =
+                                        serde_json::from_value(request["params"][0].clone())
+                                            .unwrap();
+                                    let _ = proposal_sender.unbounded_send(proposal);
+                                    Value::Null
+                                }
+                                method => panic!("Unexpected method {:?}", method),
+                            };
+                            messages.push(json!({"jsonrpc": "2.0", "result": result, "id": id}));
+                        }
+                        Event::Slot(slot_info) => {
+                            pending_slots.push_back(slot_info);
+                        }
+                        Event::Disconnect => {
+                            let _ = sender.close().await;
+                            break;
+                        }
+                        Event::Stop => {
+                            let _ = sender.close().await;
+                            return;
+                        }
+                    }
+
+                    if subscribed {
+                        for slot_info in pending_slots.drain(..) {
+                            messages.push(json!({
+                                "jsonrpc": "2.0",
+                                "method": "poc_slot_info",
+                                "params": {
+                                    "subscription": SUBSCRIPTION_ID,
+                                    "result": slot_info,
+                                },
+                            }));
+                        }
+                    }
+
+                    let mut sent = true;
+                    for message in messages {
+                        if sender.send_text(message.to_string()).await.is_err() {
+                            sent = false;
+                            break;
+                        }
+                    }
+                    if !sent || sender.flush().await.is_err() {
+                        // Farmer disconnected, wait for it to connect again
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self {
+            address,
+            slot_sender,
+            proposal_receiver,
+            disconnect_sender,
+            stop_sender: Some(stop_sender),
+        }
+    }
+
+    /// WebSocket address farmer should connect to
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Send slot to the farmer (as soon as it subscribes) without waiting for response
+    pub fn send_slot(&self, slot_info: SlotInfo) {
+        self.slot_sender.unbounded_send(slot_info).unwrap();
+    }
+
+    /// Send slot to the farmer (as soon as it subscribes) and wait for the response it proposes
+    pub async fn slot(&mut self, slot_info: SlotInfo) -> ProposedProofOfSpaceResponse {
+        let slot_number = slot_info.slot_number;
+        self.send_slot(slot_info);
+
+        let proposal = self.next_proposal().await;
+        assert_eq!(proposal.slot_number, slot_number);
+
+        proposal
+    }
+
+    /// Wait for the next response farmer proposes, whichever slot it is for
+    pub async fn next_proposal(&mut self) -> ProposedProofOfSpaceResponse {
+        timeout(PROPOSAL_TIMEOUT, self.proposal_receiver.next())
+            .await
+            .expect("Farmer didn't propose in time")
+            .expect("Farmer disconnected")
+    }
+
+    /// Close current connection with the farmer, but keep accepting new connections
+    pub fn disconnect(&self) {
+        self.disconnect_sender.unbounded_send(()).unwrap();
+    }
+
+    /// Close connection with the farmer and stop accepting new connections
+    pub fn stop(mut self) {
+        if let Some(stop_sender) = self.stop_sender.take() {
+            let _ = stop_sender.send(());
+        }
+    }
+}
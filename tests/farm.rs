@@ -0,0 +1,859 @@
+mod common;
+
+use async_std::future::timeout;
+use async_std::task;
+use common::{
+    free_address, http_request, http_request_with_headers, init, MockNode, TargetDirectory,
+};
+use futures::channel::oneshot;
+use schnorrkel::PublicKey;
+use spartan_codec::Spartan;
+use spartan_farmer::api::Status;
+use spartan_farmer::commands::{CommandError, FarmOptions, PlottingOptions, SimulationOptions};
+use spartan_farmer::farming::SlotRecord;
+use spartan_farmer::plot::{self, CommitmentStatus, Plot, PlotError};
+use spartan_farmer::rpc::{SlotInfo, Solution};
+use spartan_farmer::{commands, crypto, verification, Salt, PIECE_SIZE, PRIME_SIZE_BYTES};
+use std::fs;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const PIECE_COUNT: u64 = 64;
+const SEED: &str = "test";
+/// Maximum number of slots with the same salt farmer has to find a solution in
+const MAX_SLOTS_PER_SOLUTION: u64 = 100;
+
+struct Farmer {
+    public_key: PublicKey,
+    spartan: Spartan<PRIME_SIZE_BYTES, PIECE_SIZE>,
+    next_slot_number: u64,
+}
+
+impl Farmer {
+    async fn plot(path: &TargetDirectory) -> Self {
+        Self::plot_with_options(path, PlottingOptions::default()).await
+    }
+
+    async fn plot_with_options(path: &TargetDirectory, options: PlottingOptions) -> Self {
+        let mnemonic = crypto::generate_mnemonic();
+        let public_key = crypto::keypair_from_mnemonic(&mnemonic).unwrap().public;
+        let genesis_piece = crypto::genesis_piece_from_seed(SEED);
+
+        commands::plot(
+            path.to_path_buf(),
+            genesis_piece,
+            PIECE_COUNT,
+            PlottingOptions {
+                mnemonic: Some(mnemonic),
+                // Exercise direct I/O path, falls back to buffered writes where not supported
+                direct_io: true,
+                ..options
+            },
+        )
+        .await
+        .unwrap();
+
+        Self {
+            public_key,
+            spartan: Spartan::<PRIME_SIZE_BYTES, PIECE_SIZE>::new(genesis_piece),
+            next_slot_number: 0,
+        }
+    }
+
+    fn slot_info(&mut self, salt: Salt, next_salt: Option<Salt>) -> SlotInfo {
+        let slot_number = self.next_slot_number;
+        self.next_slot_number += 1;
+
+        SlotInfo {
+            slot_number,
+            challenge: slot_number.to_le_bytes(),
+            salt,
+            next_salt,
+            solution_range: u64::MAX,
+        }
+    }
+
+    /// Send slots with specified salts until farmer proposes a solution, solution is verified
+    /// before returning
+    async fn farm_until_solution(
+        &mut self,
+        node: &mut MockNode,
+        salt: Salt,
+        next_salt: Option<Salt>,
+    ) -> Solution {
+        for _ in 0..MAX_SLOTS_PER_SOLUTION {
+            let slot_info = self.slot_info(salt, next_salt);
+            let proposal = node.slot(slot_info.clone()).await;
+
+            if let Some(solution) = proposal.solution {
+                verification::verify_solution(
+                    &solution,
+                    &slot_info,
+                    &self.public_key,
+                    &self.spartan,
+                )
+                .unwrap();
+
+                return solution;
+            }
+
+            task::sleep(Duration::from_millis(100)).await;
+        }
+
+        panic!(
+            "No solution for salt {} in {} slots",
+            hex::encode(salt),
+            MAX_SLOTS_PER_SOLUTION
+        );
+    }
+}
+
+#[async_std::test]
+async fn farm_proposes_solutions() {
+    init();
+    let path = TargetDirectory::new("farm_proposes_solutions");
+    let mut farmer = Farmer::plot(&path).await;
+
+    let mut node = MockNode::start().await;
+    let farming = task::spawn({
+        let path = path.to_path_buf();
+        let address = node.address().to_string();
+
+        async move {
+            commands::farm(
+                path,
+                FarmOptions {
+                    ws_server: address,
+                    // Stop once node disconnects
+                    reconnect_attempts: 0,
+                    ..FarmOptions::default()
+                },
+            )
+            .await
+        }
+    });
+
+    let salt = [1u8; 8];
+    let solution = farmer.farm_until_solution(&mut node, salt, None).await;
+    assert_eq!(solution.public_key, farmer.public_key.to_bytes());
+
+    // Once commitment is created every slot should have a solution with full solution range
+    for _ in 0..5 {
+        let slot_info = farmer.slot_info(salt, None);
+        assert!(node.slot(slot_info).await.solution.is_some());
+    }
+
+    node.stop();
+    timeout(Duration::from_secs(60), farming)
+        .await
+        .expect("Farmer didn't stop after node disconnected")
+        .unwrap();
+}
+
+#[async_std::test]
+async fn plot_creates_commitments_for_salts() {
+    init();
+    let path = TargetDirectory::new("plot_creates_commitments_for_salts");
+    let salt = [1u8; 8];
+    Farmer::plot_with_options(
+        &path,
+        PlottingOptions {
+            salts: vec![salt, salt],
+            // Last batch is partial
+            batch_size: Some(10),
+            queue_size: Some(1),
+            ..PlottingOptions::default()
+        },
+    )
+    .await;
+
+    let statuses = plot::read_commitment_statuses(&path).unwrap().unwrap();
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses.get(&salt), Some(&CommitmentStatus::Created));
+
+    let plot = Plot::open_read_only(&path.to_path_buf().into())
+        .await
+        .unwrap();
+    for index in [0, PIECE_COUNT - 1] {
+        let tag = crypto::create_tag(&plot.read(index).await.unwrap(), &salt);
+        assert_eq!(
+            plot.find_by_range(tag, 0, salt).await.unwrap(),
+            Some((tag, index))
+        );
+    }
+}
+
+#[async_std::test]
+async fn plot_fails_on_write_error() {
+    init();
+    let path = TargetDirectory::new("plot_fails_on_write_error");
+    let salt = [1u8; 8];
+    // Every write fails with "No space left on device"
+    std::os::unix::fs::symlink("/dev/full", path.join("plot.bin")).unwrap();
+
+    let result = commands::plot(
+        path.to_path_buf(),
+        crypto::genesis_piece_from_seed(SEED),
+        PIECE_COUNT,
+        PlottingOptions {
+            salts: vec![salt],
+            ..PlottingOptions::default()
+        },
+    )
+    .await;
+
+    assert!(
+        matches!(result, Err(CommandError::Plot(PlotError::Io(_)))),
+        "Unexpected result {:?}",
+        result
+    );
+    let statuses = plot::read_commitment_statuses(&path).unwrap();
+    assert_ne!(
+        statuses.and_then(|statuses| statuses.get(&salt).copied()),
+        Some(CommitmentStatus::Created)
+    );
+}
+
+#[async_std::test]
+async fn plot_stores_genesis_piece_for_existing_plot() {
+    init();
+    let path = TargetDirectory::new("plot_stores_genesis_piece_for_existing_plot");
+    Farmer::plot(&path).await;
+    // Plots created by older versions don't have genesis piece stored
+    let genesis_piece_file = path.join("genesis-piece.bin");
+    fs::remove_file(&genesis_piece_file).unwrap();
+
+    let node = MockNode::start().await;
+    let result = commands::farm(
+        path.to_path_buf(),
+        FarmOptions {
+            ws_server: node.address().to_string(),
+            ..FarmOptions::default()
+        },
+    )
+    .await;
+    assert!(matches!(result, Err(CommandError::GenesisPieceNotFound)));
+
+    // Seed that doesn't match the plot is not stored
+    let result = commands::plot(
+        path.to_path_buf(),
+        crypto::genesis_piece_from_seed("other"),
+        PIECE_COUNT,
+        PlottingOptions::default(),
+    )
+    .await;
+    assert!(matches!(result, Err(CommandError::GenesisPieceMismatch)));
+    assert!(!genesis_piece_file.exists());
+
+    commands::plot(
+        path.to_path_buf(),
+        crypto::genesis_piece_from_seed(SEED),
+        PIECE_COUNT,
+        PlottingOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        fs::read(&genesis_piece_file).unwrap(),
+        crypto::genesis_piece_from_seed(SEED).to_vec()
+    );
+}
+
+#[async_std::test]
+async fn plot_fetches_salts_from_node() {
+    init();
+    let path = TargetDirectory::new("plot_fetches_salts_from_node");
+    let salt = [1u8; 8];
+    let next_salt = [2u8; 8];
+
+    let node = MockNode::start().await;
+    node.send_slot(SlotInfo {
+        slot_number: 0,
+        challenge: [0u8; PRIME_SIZE_BYTES],
+        salt,
+        next_salt: Some(next_salt),
+        solution_range: u64::MAX,
+    });
+    Farmer::plot_with_options(
+        &path,
+        PlottingOptions {
+            ws_server: Some(node.address().to_string()),
+            ..PlottingOptions::default()
+        },
+    )
+    .await;
+
+    let statuses = plot::read_commitment_statuses(&path).unwrap().unwrap();
+    assert_eq!(statuses.len(), 2);
+    assert_eq!(statuses.get(&salt), Some(&CommitmentStatus::Created));
+    assert_eq!(statuses.get(&next_salt), Some(&CommitmentStatus::Created));
+}
+
+#[async_std::test]
+async fn farm_follows_salt_changes() {
+    init();
+    let path = TargetDirectory::new("farm_follows_salt_changes");
+    let mut farmer = Farmer::plot(&path).await;
+
+    let mut node = MockNode::start().await;
+    let farming = task::spawn({
+        let path = path.to_path_buf();
+        let address = node.address().to_string();
+
+        async move {
+            commands::farm(
+                path,
+                FarmOptions {
+                    ws_server: address,
+                    reconnect_attempts: 0,
+                    // Recommitments read the whole plot, make sure it works with mmap too
+                    mmap: true,
+                    // Index is discarded and rebuilt on every recommitment
+                    tag_index: true,
+                    batch_size: Some(10),
+                    ..FarmOptions::default()
+                },
+            )
+            .await
+        }
+    });
+
+    let salt_a = [1u8; 8];
+    let salt_b = [2u8; 8];
+    let salt_c = [3u8; 8];
+    let salt_d = [4u8; 8];
+    let salt_e = [5u8; 8];
+
+    farmer.farm_until_solution(&mut node, salt_a, None).await;
+
+    // Next salt announced in advance and then becomes current as expected
+    farmer
+        .farm_until_solution(&mut node, salt_a, Some(salt_b))
+        .await;
+    farmer.farm_until_solution(&mut node, salt_b, None).await;
+
+    // Next salt is announced, but then replaced with a different one
+    farmer
+        .farm_until_solution(&mut node, salt_b, Some(salt_c))
+        .await;
+    farmer
+        .farm_until_solution(&mut node, salt_b, Some(salt_d))
+        .await;
+    farmer.farm_until_solution(&mut node, salt_d, None).await;
+
+    // Salt changes without being announced
+    farmer.farm_until_solution(&mut node, salt_e, None).await;
+
+    node.stop();
+    timeout(Duration::from_secs(60), farming)
+        .await
+        .expect("Farmer didn't stop after node disconnected")
+        .unwrap();
+}
+
+#[async_std::test]
+async fn farm_skips_superseded_slots() {
+    init();
+    let path = TargetDirectory::new("farm_skips_superseded_slots");
+    let record_file = path.join("slots.jsonl");
+    let metrics_address = free_address();
+    Farmer::plot(&path).await;
+
+    // All slots are sent at once as soon as farmer subscribes, as if farmer fell behind
+    let mut node = MockNode::start().await;
+    let last_slot_number = 9;
+    for slot_number in 0..=last_slot_number {
+        node.send_slot(SlotInfo {
+            slot_number,
+            challenge: [slot_number as u8; PRIME_SIZE_BYTES],
+            salt: [1u8; 8],
+            next_salt: None,
+            solution_range: u64::MAX / PIECE_COUNT,
+        });
+    }
+
+    let farming = task::spawn({
+        let path = path.to_path_buf();
+        let address = node.address().to_string();
+        let record_file = record_file.clone();
+
+        async move {
+            commands::farm(
+                path,
+                FarmOptions {
+                    ws_server: address,
+                    reconnect_attempts: 0,
+                    record_file: Some(record_file),
+                    metrics_address: Some(metrics_address),
+                    ..FarmOptions::default()
+                },
+            )
+            .await
+        }
+    });
+
+    // Proposals never go back in time and the newest slot is always handled
+    let mut proposals = 0;
+    let mut previous_slot_number = None;
+    loop {
+        let proposal = node.next_proposal().await;
+        assert!(previous_slot_number < Some(proposal.slot_number));
+        previous_slot_number = Some(proposal.slot_number);
+        proposals += 1;
+        if proposal.slot_number == last_slot_number {
+            break;
+        }
+    }
+    // Some slots were skipped rather than handled one by one
+    assert!(proposals <= last_slot_number);
+
+    let (_, metrics) = http_request(metrics_address, "GET", "/metrics").await;
+    assert!(metrics.contains(&format!(
+        "spartan_farmer_slots_skipped_total {}",
+        last_slot_number + 1 - proposals
+    )));
+
+    node.stop();
+    timeout(Duration::from_secs(60), farming)
+        .await
+        .expect("Farmer didn't stop after node disconnected")
+        .unwrap();
+
+    // Skipped slots are recorded too
+    let records = fs::read_to_string(&record_file)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect::<Vec<SlotRecord>>();
+    assert_eq!(records.len() as u64, last_slot_number + 1);
+    for (slot_number, record) in records.iter().enumerate() {
+        assert_eq!(record.slot_info.slot_number, slot_number as u64);
+    }
+}
+
+#[async_std::test]
+async fn simulate_preserves_commitments() {
+    init();
+    let path = TargetDirectory::new("simulate_preserves_commitments");
+    let salt = [1u8; 8];
+    Farmer::plot_with_options(
+        &path,
+        PlottingOptions {
+            salts: vec![salt],
+            ..PlottingOptions::default()
+        },
+    )
+    .await;
+
+    commands::simulate(
+        path.to_path_buf(),
+        SimulationOptions {
+            slots: 4,
+            slot_duration: Duration::from_millis(10),
+            salt_rotation: 2,
+            next_salt_announcement: 1,
+            solution_range: None,
+        },
+    )
+    .await
+    .unwrap();
+
+    let statuses = plot::read_commitment_statuses(&path).unwrap().unwrap();
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses.get(&salt), Some(&CommitmentStatus::Created));
+}
+
+#[async_std::test]
+async fn farm_records_slots_for_replay() {
+    init();
+    let path = TargetDirectory::new("farm_records_slots_for_replay");
+    let record_file = path.join("slots.jsonl");
+    let mut farmer = Farmer::plot(&path).await;
+
+    let mut node = MockNode::start().await;
+    let farming = task::spawn({
+        let path = path.to_path_buf();
+        let address = node.address().to_string();
+        let record_file = record_file.clone();
+
+        async move {
+            commands::farm(
+                path,
+                FarmOptions {
+                    ws_server: address,
+                    reconnect_attempts: 0,
+                    record_file: Some(record_file),
+                    ..FarmOptions::default()
+                },
+            )
+            .await
+        }
+    });
+
+    let salt_a = [1u8; 8];
+    let salt_b = [2u8; 8];
+
+    farmer
+        .farm_until_solution(&mut node, salt_a, Some(salt_b))
+        .await;
+    let solution = farmer.farm_until_solution(&mut node, salt_b, None).await;
+
+    node.stop();
+    timeout(Duration::from_secs(60), farming)
+        .await
+        .expect("Farmer didn't stop after node disconnected")
+        .unwrap();
+
+    let records = fs::read_to_string(&record_file)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect::<Vec<SlotRecord>>();
+    assert_eq!(records.len() as u64, farmer.next_slot_number);
+    for (slot_number, record) in records.iter().enumerate() {
+        assert_eq!(record.slot_info.slot_number, slot_number as u64);
+    }
+    let last_record = records.last().unwrap();
+    assert_eq!(last_record.slot_info.salt, salt_b);
+    assert_eq!(last_record.solution.as_ref().unwrap().tag, solution.tag);
+
+    // Commitment that is not in the record, as if farmer has moved on since then
+    let salt_c = [3u8; 8];
+    {
+        let plot = Plot::open_or_create(&path.to_path_buf().into())
+            .await
+            .unwrap();
+        plot.create_commitment(salt_c).await.unwrap();
+        let (tx, rx) = oneshot::channel();
+        let _handler = plot.on_close(move || {
+            let _ = tx.send(());
+        });
+        drop(plot);
+        rx.await.unwrap();
+    }
+
+    commands::replay(path.to_path_buf(), record_file.clone())
+        .await
+        .unwrap();
+
+    // Replay leaves farmer's commitments intact
+    let statuses = plot::read_commitment_statuses(&path).unwrap().unwrap();
+    assert_eq!(statuses.len(), 2);
+    assert_eq!(statuses.get(&salt_b), Some(&CommitmentStatus::Created));
+    assert_eq!(statuses.get(&salt_c), Some(&CommitmentStatus::Created));
+
+    fs::write(&record_file, "not a record\n").unwrap();
+    let result = commands::replay(path.to_path_buf(), record_file).await;
+    assert!(matches!(
+        result,
+        Err(commands::CommandError::InvalidRecord { line: 1, .. })
+    ));
+}
+
+#[async_std::test]
+async fn farm_exports_metrics() {
+    init();
+    let path = TargetDirectory::new("farm_exports_metrics");
+    let metrics_address = free_address();
+    let mut farmer = Farmer::plot(&path).await;
+
+    let mut node = MockNode::start().await;
+    let farming = task::spawn({
+        let path = path.to_path_buf();
+        let address = node.address().to_string();
+
+        async move {
+            commands::farm(
+                path,
+                FarmOptions {
+                    ws_server: address,
+                    reconnect_attempts: 0,
+                    metrics_address: Some(metrics_address),
+                    ..FarmOptions::default()
+                },
+            )
+            .await
+        }
+    });
+
+    let salt = [1u8; 8];
+    farmer.farm_until_solution(&mut node, salt, None).await;
+
+    let (status, metrics) = http_request(metrics_address, "GET", "/metrics").await;
+    assert_eq!(status, 200);
+    assert!(metrics.contains(&format!(
+        "spartan_farmer_slots_received_total {}",
+        farmer.next_slot_number
+    )));
+    // Submission is counted after node responds, so only check counters updated before that
+    assert!(metrics.contains("spartan_farmer_solutions_found_total 1"));
+    assert!(metrics.contains("spartan_farmer_rpc_connections_total 1"));
+    assert!(metrics.contains(&format!("spartan_farmer_plot_pieces {}", PIECE_COUNT)));
+    assert!(metrics.contains(&format!(
+        "spartan_farmer_commitment_progress_ratio{{salt=\"{}\"}} 1",
+        hex::encode(salt)
+    )));
+    assert!(metrics.contains("spartan_farmer_find_by_range_latency_seconds_count"));
+    assert!(metrics.contains("# TYPE spartan_farmer_plot_queue_wait_seconds_total counter"));
+    assert!(metrics
+        .contains("spartan_farmer_plot_queue_dispatched_total{priority=\"solution_lookup\"}"));
+
+    let (status, _) = http_request(metrics_address, "GET", "/unknown").await;
+    assert_eq!(status, 404);
+
+    node.stop();
+    timeout(Duration::from_secs(60), farming)
+        .await
+        .expect("Farmer didn't stop after node disconnected")
+        .unwrap();
+}
+
+#[async_std::test]
+async fn farm_reconnects_to_node() {
+    init();
+    let path = TargetDirectory::new("farm_reconnects_to_node");
+    let metrics_address = free_address();
+    let mut farmer = Farmer::plot(&path).await;
+
+    let mut node = MockNode::start().await;
+    let farming = task::spawn({
+        let path = path.to_path_buf();
+        let address = node.address().to_string();
+
+        async move {
+            commands::farm(
+                path,
+                FarmOptions {
+                    ws_server: address,
+                    reconnect_attempts: 1,
+                    metrics_address: Some(metrics_address),
+                    ..FarmOptions::default()
+                },
+            )
+            .await
+        }
+    });
+
+    let salt = [1u8; 8];
+    farmer.farm_until_solution(&mut node, salt, None).await;
+
+    // Farmer subscribes again after reconnecting and keeps farming
+    node.disconnect();
+    let slot_info = farmer.slot_info(salt, None);
+    assert!(node.slot(slot_info).await.solution.is_some());
+
+    let (status, metrics) = http_request(metrics_address, "GET", "/metrics").await;
+    assert_eq!(status, 200);
+    assert!(metrics.contains("spartan_farmer_rpc_connections_total 2"));
+
+    // Farmer gives up once node can't be reached anymore
+    node.stop();
+    let result = timeout(Duration::from_secs(60), farming)
+        .await
+        .expect("Farmer didn't stop after node went away");
+    assert!(matches!(result, Err(CommandError::Rpc(_))));
+}
+
+/// Request to farmer's API authorized with token from data directory
+async fn api_request(
+    path: &TargetDirectory,
+    address: SocketAddr,
+    method: &str,
+    api_path: &str,
+) -> (u16, String) {
+    let token = fs::read_to_string(path.join("api-token")).unwrap();
+    http_request_with_headers(
+        address,
+        method,
+        api_path,
+        &[
+            ("Host", &address.to_string()),
+            ("Authorization", &format!("Bearer {}", token)),
+        ],
+    )
+    .await
+}
+
+async fn api_status(path: &TargetDirectory, address: SocketAddr) -> Status {
+    let (status, body) = api_request(path, address, "GET", "/status").await;
+    assert_eq!(status, 200, "{}", body);
+
+    serde_json::from_str(&body).unwrap()
+}
+
+/// Wait until commitment for salt is created according to the API
+async fn wait_for_commitment(path: &TargetDirectory, address: SocketAddr, salt: Salt) {
+    for _ in 0..MAX_SLOTS_PER_SOLUTION {
+        if api_status(path, address)
+            .await
+            .commitments
+            .get(&hex::encode(salt))
+            == Some(&CommitmentStatus::Created)
+        {
+            return;
+        }
+        task::sleep(Duration::from_millis(100)).await;
+    }
+
+    panic!("Commitment for salt {} was not created", hex::encode(salt));
+}
+
+#[async_std::test]
+async fn farm_is_controlled_by_api() {
+    init();
+    let path = TargetDirectory::new("farm_is_controlled_by_api");
+    let api_address = free_address();
+    let mut farmer = Farmer::plot(&path).await;
+
+    let mut node = MockNode::start().await;
+    let farming = task::spawn({
+        let path = path.to_path_buf();
+        let address = node.address().to_string();
+
+        async move {
+            commands::farm(
+                path,
+                FarmOptions {
+                    ws_server: address,
+                    api_address: Some(api_address),
+                    ..FarmOptions::default()
+                },
+            )
+            .await
+        }
+    });
+
+    let salt_a = [1u8; 8];
+    let salt_b = [2u8; 8];
+    let salt_c = [3u8; 8];
+
+    farmer.farm_until_solution(&mut node, salt_a, None).await;
+
+    let status = api_status(&path, api_address).await;
+    assert!(status.connected);
+    assert_eq!(status.node, node.address());
+    assert_eq!(status.current_salt, Some(hex::encode(salt_a)));
+    assert_eq!(status.next_salt, None);
+    assert!(!status.recommitment_paused);
+    assert_eq!(
+        status.last_slot.unwrap().slot_number,
+        farmer.next_slot_number - 1
+    );
+
+    // Commitment for announced salt is deferred while recommitment is paused
+    let (status, _) = api_request(&path, api_address, "POST", "/recommitment/pause").await;
+    assert_eq!(status, 200);
+    let slot_info = farmer.slot_info(salt_a, Some(salt_b));
+    node.slot(slot_info).await;
+    let status = api_status(&path, api_address).await;
+    assert!(status.recommitment_paused);
+    assert_eq!(status.next_salt, Some(hex::encode(salt_b)));
+    assert!(!status.commitments.contains_key(&hex::encode(salt_b)));
+
+    let (status, _) = api_request(&path, api_address, "POST", "/recommitment/resume").await;
+    assert_eq!(status, 200);
+    wait_for_commitment(&path, api_address, salt_b).await;
+
+    // Commitment can be forced for arbitrary salt
+    let (status, _) = api_request(&path, api_address, "POST", "/commitments/not-a-salt").await;
+    assert_eq!(status, 400);
+    let (status, _) = api_request(
+        &path,
+        api_address,
+        "POST",
+        &format!("/commitments/{}", hex::encode(salt_c)),
+    )
+    .await;
+    assert_eq!(status, 200);
+    wait_for_commitment(&path, api_address, salt_c).await;
+
+    // Requests that are not authorized or may come from browser are rejected
+    let token = fs::read_to_string(path.join("api-token")).unwrap();
+    let authorization = format!("Bearer {}", token);
+    let host = api_address.to_string();
+    let (status, _) = http_request(api_address, "POST", "/shutdown").await;
+    assert_eq!(status, 401);
+    let (status, _) = http_request_with_headers(
+        api_address,
+        "POST",
+        "/shutdown",
+        &[("Host", &host), ("Authorization", "Bearer wrong")],
+    )
+    .await;
+    assert_eq!(status, 401);
+    let (status, _) = http_request_with_headers(
+        api_address,
+        "POST",
+        "/shutdown",
+        &[
+            ("Host", &host),
+            ("Authorization", &authorization),
+            ("Origin", "http://example.com"),
+        ],
+    )
+    .await;
+    assert_eq!(status, 403);
+    let (status, _) = http_request_with_headers(
+        api_address,
+        "GET",
+        "/status",
+        &[
+            ("Host", &format!("example.com:{}", api_address.port())),
+            ("Authorization", &authorization),
+        ],
+    )
+    .await;
+    assert_eq!(status, 403);
+
+    let (status, _) = api_request(&path, api_address, "POST", "/status").await;
+    assert_eq!(status, 405);
+    let (status, _) = api_request(&path, api_address, "GET", "/shutdown").await;
+    assert_eq!(status, 405);
+    let (status, _) = api_request(&path, api_address, "POST", "/shutdown").await;
+    assert_eq!(status, 200);
+    timeout(Duration::from_secs(60), farming)
+        .await
+        .expect("Farmer didn't stop after shutdown request")
+        .unwrap();
+}
+
+#[async_std::test]
+async fn farm_refuses_to_expose_api() {
+    init();
+    let path = TargetDirectory::new("farm_refuses_to_expose_api");
+    let address: SocketAddr = "0.0.0.0:0".parse().unwrap();
+
+    let result = commands::farm(
+        path.to_path_buf(),
+        FarmOptions {
+            api_address: Some(address),
+            ..FarmOptions::default()
+        },
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(CommandError::ApiAddressNotLoopback(result_address)) if result_address == address
+    ));
+}
+
+#[async_std::test]
+async fn farm_fails_without_plot() {
+    init();
+    let path = TargetDirectory::new("farm_fails_without_plot");
+
+    let node = MockNode::start().await;
+
+    let result = commands::farm(
+        path.to_path_buf(),
+        FarmOptions {
+            ws_server: node.address().to_string(),
+            ..FarmOptions::default()
+        },
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(commands::CommandError::IdentityNotFound)
+    ));
+}